@@ -0,0 +1,204 @@
+//! Misbehaving peers and the misbehaviour honest peers detect.
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::peer::PeerId;
+
+/// How a peer behaves towards others.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Role {
+    #[default]
+    Honest,
+
+    /// Sends random bytes of random length instead of reconciliation sketches.
+    GarbageSketch,
+
+    /// Sends sketches of a different capacity than the rest of the network uses.
+    WrongCapacitySketch,
+
+    /// Answers reconciliation requests with huge lists of random missing IDs.
+    OversizedResult,
+
+    /// Floods connected peers with requests for random transactions.
+    TxRequestSpam,
+
+    /// Accepts transactions but never relays them.
+    BlackHole,
+
+    /// Reconciles to receive transactions, but never answers
+    /// reconciliation or transaction requests.
+    FreeRider,
+
+    /// Doesn't relay transactions created by given peers.
+    SelectiveRelay(BTreeSet<PeerId>),
+
+    /// Replaces every transaction it receives with a different one with the same
+    /// unsalted short ID, and floods it to all connections.
+    CollidingTx,
+
+    /// Eclipse attacker: cuts given victim peers off the network by relaying
+    /// neither their transactions to others, nor others' transactions to them.
+    Eclipse(BTreeSet<PeerId>),
+}
+
+impl Role {
+    pub fn is_honest(&self) -> bool {
+        *self == Role::Honest
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        let name = match self {
+            Role::Honest => "honest",
+            Role::GarbageSketch => "garbage-sketch",
+            Role::WrongCapacitySketch => "wrong-capacity-sketch",
+            Role::OversizedResult => "oversized-result",
+            Role::TxRequestSpam => "tx-request-spam",
+            Role::BlackHole => "black-hole",
+            Role::FreeRider => "free-rider",
+            Role::CollidingTx => "colliding-tx",
+            Role::SelectiveRelay(origins) => {
+                return write!(f, "selective-relay:{}", format_peers(origins))
+            }
+            Role::Eclipse(victims) => return write!(f, "eclipse:{}", format_peers(victims)),
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "honest" => Ok(Role::Honest),
+            "garbage-sketch" => Ok(Role::GarbageSketch),
+            "wrong-capacity-sketch" => Ok(Role::WrongCapacitySketch),
+            "oversized-result" => Ok(Role::OversizedResult),
+            "tx-request-spam" => Ok(Role::TxRequestSpam),
+            "black-hole" => Ok(Role::BlackHole),
+            "free-rider" => Ok(Role::FreeRider),
+            "colliding-tx" => Ok(Role::CollidingTx),
+            _ => {
+                if let Some(origins) = s.strip_prefix("selective-relay:") {
+                    Ok(Role::SelectiveRelay(parse_peers(origins)?))
+                } else if let Some(victims) = s.strip_prefix("eclipse:") {
+                    Ok(Role::Eclipse(parse_peers(victims)?))
+                } else {
+                    Err(format!("unknown role: {}", s))
+                }
+            }
+        }
+    }
+}
+
+fn format_peers(peers: &BTreeSet<PeerId>) -> String {
+    peers
+        .iter()
+        .map(|id| format!("{:?}", id))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parses a comma-separated list of peers, e.g. `priv0,priv1`.
+pub fn parse_peers(s: &str) -> Result<BTreeSet<PeerId>, String> {
+    s.split(',').map(str::parse).collect()
+}
+
+/// Parses a role of a single peer given as `<peer>=<role>`, e.g. `priv3=garbage-sketch`.
+pub fn parse_role(s: &str) -> Result<(PeerId, Role), String> {
+    let (peer, role) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <peer>=<role>, got: {}", s))?;
+
+    Ok((peer.parse()?, role.parse()?))
+}
+
+/// Parses a share of the network given a role as `<role>=<fraction>`, e.g. `black-hole=0.1`.
+pub fn parse_role_fraction(s: &str) -> Result<(Role, f64), String> {
+    let (role, fraction) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected <role>=<fraction>, got: {}", s))?;
+    let fraction = fraction
+        .parse::<f64>()
+        .ok()
+        .filter(|v| (0.0..=1.0).contains(v))
+        .ok_or_else(|| format!("invalid fraction: {}", fraction))?;
+
+    Ok((role.parse()?, fraction))
+}
+
+/// Misbehaviour of another peer, detected by an honest one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// Sketch that can't be deserialized or merged with the local one.
+    InvalidSketch,
+
+    /// Reconciliation result with more IDs than a sketch can decode.
+    OversizedResult,
+
+    /// Transaction requests above the rate limit.
+    TxRequestFlood,
+}
+
+impl Misbehaviour {
+    /// Score added to a peer for this misbehaviour.
+    pub fn score(self) -> u64 {
+        match self {
+            Misbehaviour::InvalidSketch => 100,
+            Misbehaviour::OversizedResult => 100,
+            Misbehaviour::TxRequestFlood => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_role, parse_role_fraction, Role};
+    use crate::peer::PeerId;
+
+    #[test]
+    pub fn test_parse_roles() {
+        for role in [
+            Role::Honest,
+            Role::GarbageSketch,
+            Role::WrongCapacitySketch,
+            Role::OversizedResult,
+            Role::TxRequestSpam,
+            Role::BlackHole,
+            Role::FreeRider,
+            Role::CollidingTx,
+            Role::SelectiveRelay(
+                [PeerId::Private(0), PeerId::Public(2)]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            ),
+            Role::Eclipse([PeerId::Private(4)].iter().cloned().collect()),
+        ]
+        .iter()
+        {
+            assert_eq!(role.to_string().parse::<Role>(), Ok(role.clone()));
+        }
+
+        assert_eq!(
+            parse_role("priv3=tx-request-spam"),
+            Ok((PeerId::Private(3), Role::TxRequestSpam))
+        );
+        assert!(parse_role("priv3").is_err());
+        assert!(parse_role("priv3=sybil").is_err());
+
+        assert_eq!(
+            parse_role_fraction("selective-relay:priv1=0.5"),
+            Ok((
+                Role::SelectiveRelay([PeerId::Private(1)].iter().cloned().collect()),
+                0.5
+            ))
+        );
+        assert!(parse_role_fraction("black-hole=1.5").is_err());
+    }
+}
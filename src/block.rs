@@ -0,0 +1,185 @@
+//! Blocks: miners confirm mempool transactions in blocks that propagate through the network.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{Debug, Error, Formatter};
+use std::time::Duration;
+
+use crate::mempool::Mempool;
+use crate::messages::Tx;
+use crate::peer::PeerId;
+use crate::tx_registry::TxId;
+
+/// Size of a block header in bytes.
+pub const BLOCK_HEADER_BYTES: u64 = 80;
+
+/// Weight of a byte of transaction data that isn't a witness.
+pub const WITNESS_SCALE_FACTOR: u64 = 4;
+
+/// Weight blocks have at most, unless set otherwise.
+pub const DEFAULT_MAX_BLOCK_WEIGHT: u64 = 4_000_000;
+
+/// Mean time between blocks in the whole network, in seconds, unless set otherwise.
+pub const DEFAULT_BLOCK_INTERVAL_SEC: f64 = 600.0;
+
+/// Describes which peers mine blocks and how often.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MiningConfig {
+    /// Peers that mine blocks. They share the hash rate equally.
+    pub miners: BTreeSet<PeerId>,
+
+    /// Mean time between blocks mined by any of the miners, in simulated time.
+    /// Blocks are found on a Poisson schedule.
+    pub block_interval: Duration,
+
+    /// Weight blocks have at most.
+    pub max_block_weight: u64,
+}
+
+impl MiningConfig {
+    /// Mean time between blocks mined by a single miner, in seconds.
+    pub fn miner_interval(&self) -> f64 {
+        self.block_interval.as_secs_f64() * self.miners.len() as f64
+    }
+}
+
+/// Identifies a block by its miner and the number of blocks the miner found before it.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlockId {
+    pub miner: PeerId,
+    pub number: u32,
+}
+
+impl Debug for BlockId {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{:?}#{}", self.miner, self.number)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub id: BlockId,
+
+    /// Simulated time the block was found at.
+    pub created: Duration,
+
+    pub txs: Vec<Tx>,
+}
+
+impl Block {
+    /// Assembles a block of the best paying transactions from `mempool` that fit
+    /// into `max_weight`.
+    ///
+    /// `parent` tells which transaction a transaction spends. Children only go after
+    /// their parents, so a child is left out until its parent is in the block.
+    pub fn assemble<F>(
+        id: BlockId,
+        created: Duration,
+        mempool: &Mempool,
+        max_weight: u64,
+        parent: F,
+    ) -> Self
+    where
+        F: Fn(TxId) -> Option<TxId>,
+    {
+        let mut weight = BLOCK_HEADER_BYTES * WITNESS_SCALE_FACTOR;
+        let mut txs = vec![];
+        let mut included = HashSet::new();
+
+        // Children left out for their parents are considered again right after them,
+        // so a single pass over the mempool is enough
+        let mut waiting = HashMap::<TxId, Vec<Tx>>::new();
+        for tx in mempool.iter_by_fee_rate() {
+            if let Some(parent) = parent(tx.id) {
+                if mempool.contains(&parent) && !included.contains(&parent) {
+                    waiting.entry(parent).or_default().push(*tx);
+                    continue;
+                }
+            }
+
+            let mut candidates = vec![*tx];
+            while let Some(tx) = candidates.pop() {
+                let tx_weight = u64::from(tx.size) * WITNESS_SCALE_FACTOR;
+                if weight + tx_weight <= max_weight {
+                    weight += tx_weight;
+                    txs.push(tx);
+                    included.insert(tx.id);
+
+                    // Best paying children first
+                    if let Some(children) = waiting.remove(&tx.id) {
+                        candidates.extend(children.into_iter().rev());
+                    }
+                }
+            }
+        }
+
+        Block { id, created, txs }
+    }
+
+    /// Total size of transactions in the block in bytes.
+    pub fn txs_size(&self) -> u64 {
+        self.txs.iter().map(|tx| u64::from(tx.size)).sum()
+    }
+
+    pub fn weight(&self) -> u64 {
+        (BLOCK_HEADER_BYTES + self.txs_size()) * WITNESS_SCALE_FACTOR
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Block, BlockId, BLOCK_HEADER_BYTES, WITNESS_SCALE_FACTOR};
+    use crate::mempool::{Mempool, MempoolConfig};
+    use crate::messages::Tx;
+    use crate::peer::PeerId;
+    use crate::tx_registry::TxId;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_assemble_block() {
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        let txs = [
+            (0, 400, 1000),
+            (1, 100, 3000),
+            (2, 300, 2000),
+            (3, 100, 1000),
+        ];
+        for (id, size, fee_rate) in txs.iter().cloned() {
+            let tx = Tx {
+                id: TxId(id),
+                size,
+                fee_rate,
+            };
+            mempool.insert(tx, Duration::from_secs(0)).unwrap();
+        }
+
+        let id = BlockId {
+            miner: PeerId::Public(0),
+            number: 0,
+        };
+        let max_weight = (BLOCK_HEADER_BYTES + 500) * WITNESS_SCALE_FACTOR;
+        let block = Block::assemble(id, Duration::from_secs(1), &mempool, max_weight, |_| None);
+
+        // The best paying transactions go first, and smaller ones fill the rest
+        let ids = block.txs.iter().map(|tx| tx.id.0).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(block.weight(), max_weight);
+        assert_eq!(format!("{:?}", block.id), "pub0#0");
+
+        // Children go after their parents, even if they pay more
+        let parent = |id: TxId| if id.0 == 1 { Some(TxId(3)) } else { None };
+        let block = Block::assemble(id, Duration::from_secs(1), &mempool, max_weight, parent);
+        let ids = block.txs.iter().map(|tx| tx.id.0).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 3, 1]);
+
+        // Grandchildren wait for their parents too
+        let parent = |id: TxId| match id.0 {
+            1 => Some(TxId(3)),
+            2 => Some(TxId(1)),
+            _ => None,
+        };
+        let max_weight = (BLOCK_HEADER_BYTES + 600) * WITNESS_SCALE_FACTOR;
+        let block = Block::assemble(id, Duration::from_secs(1), &mempool, max_weight, parent);
+        let ids = block.txs.iter().map(|tx| tx.id.0).collect::<Vec<_>>();
+        assert_eq!(ids, vec![0, 3, 1]);
+    }
+}
@@ -0,0 +1,17 @@
+//! Node churn: peers going offline and coming back during the simulation.
+
+use crate::distribution::ValueDistribution;
+
+/// Describes how long peers stay online and offline.
+///
+/// A peer that goes offline drops all its connections. When it comes back,
+/// it reconnects to peers it knew about and catches up with the network
+/// by the means of its relay strategy.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChurnConfig {
+    /// Time a peer stays online, in simulated seconds.
+    pub session: ValueDistribution,
+
+    /// Time a peer stays offline, in simulated seconds.
+    pub downtime: ValueDistribution,
+}
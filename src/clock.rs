@@ -0,0 +1,76 @@
+//! Simulated time.
+//!
+//! Peers run on actix timers, so simulated time passes along with the wall clock,
+//! sped up or slowed down by a constant time scale.
+
+use std::time::{Duration, Instant};
+
+/// Maps simulated time to the wall-clock time of actix timers.
+#[derive(Debug, Copy, Clone)]
+pub struct Clock {
+    start: Instant,
+    time_scale: f64,
+}
+
+impl Clock {
+    /// Starts a clock whose simulated time runs `time_scale` times faster than the wall clock.
+    pub fn new(time_scale: f64) -> Self {
+        Clock {
+            start: Instant::now(),
+            time_scale,
+        }
+    }
+
+    /// Simulated time elapsed since the start of the simulation.
+    pub fn now(&self) -> Duration {
+        self.start.elapsed().mul_f64(self.time_scale)
+    }
+
+    /// Wall-clock delay that corresponds to a simulated `delay`.
+    pub fn real(&self, delay: Duration) -> Duration {
+        delay.div_f64(self.time_scale)
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::new(1.0)
+    }
+}
+
+/// Parses a time scale, a positive number.
+pub fn parse_time_scale(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v > 0.0)
+        .ok_or_else(|| format!("invalid time scale: {}", s))
+}
+
+/// Parses a positive number of seconds, e.g. `0.5`.
+pub fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|v| *v > 0.0)
+        .and_then(|v| Duration::try_from_secs_f64(v).ok())
+        .ok_or_else(|| format!("invalid number of seconds: {}", s))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_seconds, parse_time_scale};
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_parse_time() {
+        assert_eq!(parse_time_scale("20"), Ok(20.0));
+        assert_eq!(parse_seconds("0.5"), Ok(Duration::from_millis(500)));
+
+        for s in ["0", "-1", "NaN", "inf", "x"].iter() {
+            assert!(parse_time_scale(s).is_err());
+            assert!(parse_seconds(s).is_err());
+        }
+
+        // Too long to fit a `Duration`
+        assert!(parse_seconds("1e300").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! BIP152 compact blocks: blocks announced by short IDs of their transactions, which
+//! receivers look up in their mempools, requesting only the ones they lack.
+
+use siphasher::sip::SipHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+use crate::block::{Block, BlockId};
+use crate::short_ids::ShortIdKey;
+use crate::tx_registry::{TxId, TxRegistry};
+
+/// Size of a compact block short ID in bytes.
+pub const SHORT_ID_BYTES: u64 = 6;
+
+/// Size of a block hash in bytes.
+pub const BLOCK_HASH_BYTES: u64 = 32;
+
+/// Size of the nonce a sender salts its compact block short IDs with, in bytes.
+pub const NONCE_BYTES: u64 = 8;
+
+const SHORT_ID_MASK: u64 = (1 << (SHORT_ID_BYTES * 8)) - 1;
+
+/// SipHash key of the short IDs of block `id`, announced with `nonce`.
+pub fn block_key(id: BlockId, nonce: u64) -> ShortIdKey {
+    let derive = |tag: &[u8]| {
+        let mut hasher = SipHasher::new_with_keys(u64::from(id.miner), u64::from(id.number));
+        hasher.write_u64(nonce);
+        hasher.write(tag);
+        hasher.finish()
+    };
+
+    ShortIdKey(derive(b"cmpct-k0"), derive(b"cmpct-k1"))
+}
+
+/// Short ID of a registered transaction in a compact block.
+pub fn short_id(registry: &TxRegistry, id: TxId, key: ShortIdKey) -> u64 {
+    registry.short_id(id, key) & SHORT_ID_MASK
+}
+
+/// Transactions of a mempool by their compact block short IDs, `None` if more
+/// than one transaction has the same short ID.
+///
+/// Short IDs are keyed by the block, so the index can't be kept up to date as the
+/// mempool changes: every compact block costs a SipHash of each transaction's 32-byte
+/// hash, like it does in BIP152 nodes.
+pub fn mempool_index<I>(
+    registry: &TxRegistry,
+    txs: I,
+    key: ShortIdKey,
+) -> HashMap<u64, Option<TxId>>
+where
+    I: IntoIterator<Item = TxId>,
+{
+    let mut index = HashMap::new();
+    for id in txs {
+        index
+            .entry(short_id(registry, id, key))
+            .and_modify(|known| *known = None)
+            .or_insert(Some(id));
+    }
+
+    index
+}
+
+/// Outcome of looking up the transactions of a compact block in a mempool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reconstruction {
+    /// All transactions are in the mempool.
+    Complete,
+
+    /// Transactions at given indexes have to be requested.
+    Missing(Vec<usize>),
+
+    /// A short ID matched a different transaction, so the reconstructed block is invalid
+    /// and all of it has to be requested.
+    Failed,
+}
+
+/// Reconstructs `block` announced with `short_ids` from a mempool `index`.
+///
+/// The receiver only has the short IDs; `block` tells whether the transactions they
+/// matched are the right ones.
+pub fn reconstruct(
+    block: &Block,
+    short_ids: &[u64],
+    index: &HashMap<u64, Option<TxId>>,
+) -> Reconstruction {
+    let mut missing = vec![];
+    for (i, (tx, short_id)) in block.txs.iter().zip(short_ids.iter()).enumerate() {
+        match index.get(short_id) {
+            Some(Some(id)) if *id != tx.id => return Reconstruction::Failed,
+            Some(Some(_)) => {}
+            _ => missing.push(i),
+        }
+    }
+
+    if missing.is_empty() {
+        Reconstruction::Complete
+    } else {
+        Reconstruction::Missing(missing)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{block_key, mempool_index, reconstruct, short_id, Reconstruction};
+    use crate::block::{Block, BlockId};
+    use crate::peer::PeerId;
+    use crate::tx_registry::{TxId, TxRegistry};
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_reconstruct() {
+        let registry = TxRegistry::new();
+        let txs = (0..4u8)
+            .map(|i| registry.register(vec![i; 100], PeerId::Private(0), Duration::from_secs(0)))
+            .collect::<Vec<_>>();
+        let id = BlockId {
+            miner: PeerId::Public(0),
+            number: 0,
+        };
+        let block = Block {
+            id,
+            created: Duration::from_secs(0),
+            txs: txs.clone(),
+        };
+        let key = block_key(id, 7);
+        assert_ne!(key, block_key(id, 8));
+
+        let short_ids = txs
+            .iter()
+            .map(|tx| short_id(&registry, tx.id, key))
+            .collect::<Vec<_>>();
+        assert!(short_ids.iter().all(|id| *id < 1 << 48));
+
+        let all = mempool_index(&registry, txs.iter().map(|tx| tx.id), key);
+        assert_eq!(
+            reconstruct(&block, &short_ids, &all),
+            Reconstruction::Complete
+        );
+
+        let some = mempool_index(&registry, vec![txs[0].id, txs[2].id], key);
+        assert_eq!(
+            reconstruct(&block, &short_ids, &some),
+            Reconstruction::Missing(vec![1, 3])
+        );
+
+        // Ambiguous short IDs are requested, wrong matches invalidate the block
+        let mut index = all.clone();
+        index.insert(short_ids[1], None);
+        assert_eq!(
+            reconstruct(&block, &short_ids, &index),
+            Reconstruction::Missing(vec![1])
+        );
+        index.insert(short_ids[2], Some(TxId(99)));
+        assert_eq!(
+            reconstruct(&block, &short_ids, &index),
+            Reconstruction::Failed
+        );
+    }
+}
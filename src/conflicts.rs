@@ -0,0 +1,140 @@
+//! Conflicting transactions: replacements that pay more and double spends racing
+//! through the network.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::peer::PeerId;
+use crate::tx_registry::TxId;
+
+/// How long peers disagreed on which of conflicting transactions to keep.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConflictStats {
+    /// Number of outputs spent by conflicting transactions.
+    pub num_conflicts: usize,
+
+    /// Time it took all peers to keep the same transaction, in simulated seconds, for
+    /// every settled conflict. It's counted from the creation of that transaction, or of
+    /// the first one conflicting with it if peers settled on the original.
+    pub split_times: Vec<f64>,
+
+    /// Conflicts peers still disagreed on at the end of the simulation.
+    pub unsettled: usize,
+}
+
+impl ConflictStats {
+    /// Computes the stats from conflicting transactions by the output they spend
+    /// (`conflicts`), the simulated time transactions were created at in seconds, and
+    /// the transaction each peer kept last for each output along with the time it did.
+    pub fn new<F>(
+        conflicts: &HashMap<TxId, Vec<TxId>>,
+        created: F,
+        versions: &HashMap<PeerId, HashMap<TxId, (TxId, f64)>>,
+    ) -> Self
+    where
+        F: Fn(TxId) -> f64,
+    {
+        let mut split_times = vec![];
+        let mut unsettled = 0;
+        for (outpoint, txs) in conflicts {
+            let kept = versions
+                .values()
+                .filter_map(|versions| versions.get(outpoint))
+                .collect::<Vec<_>>();
+            if kept.windows(2).any(|pair| pair[0].0 != pair[1].0) {
+                unsettled += 1;
+                continue;
+            }
+
+            // Peers can only disagree once there's a second transaction
+            let settled = kept.first().map_or(txs[0], |(id, _)| *id);
+            let start = created(settled).max(created(txs[1]));
+            let end = kept.iter().map(|(_, since)| *since).fold(start, f64::max);
+            split_times.push(end - start);
+        }
+        split_times.sort_by(|a, b| a.partial_cmp(b).expect("split time is a number"));
+
+        ConflictStats {
+            num_conflicts: conflicts.len(),
+            split_times,
+            unsettled,
+        }
+    }
+
+    /// Mean time settled conflicts kept peers split, in simulated seconds.
+    pub fn mean_split_time(&self) -> f64 {
+        if self.split_times.is_empty() {
+            return 0.0;
+        }
+
+        self.split_times.iter().sum::<f64>() / self.split_times.len() as f64
+    }
+
+    /// Longest time a settled conflict kept peers split, in simulated seconds.
+    pub fn max_split_time(&self) -> f64 {
+        self.split_times.last().cloned().unwrap_or_default()
+    }
+}
+
+impl Display for ConflictStats {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{} conflicts, {} settled after mean {:.3}s, max {:.3}s, {} still split",
+            self.num_conflicts,
+            self.split_times.len(),
+            self.mean_split_time(),
+            self.max_split_time(),
+            self.unsettled
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConflictStats;
+    use crate::peer::PeerId;
+    use crate::tx_registry::TxId;
+    use std::collections::HashMap;
+
+    #[test]
+    pub fn test_conflict_stats() {
+        let mut conflicts = HashMap::new();
+        conflicts.insert(TxId(0), vec![TxId(0), TxId(1)]);
+        conflicts.insert(TxId(2), vec![TxId(2), TxId(3)]);
+        conflicts.insert(TxId(4), vec![TxId(4), TxId(5), TxId(6)]);
+        let created = |id: TxId| f64::from(id.0);
+
+        // Both peers switched to the replacements of 0 and 4, but disagree on 2
+        let mut versions = HashMap::new();
+        versions.insert(
+            PeerId::Public(0),
+            [
+                (TxId(0), (TxId(1), 1.5)),
+                (TxId(2), (TxId(2), 2.0)),
+                (TxId(4), (TxId(6), 6.5)),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+        versions.insert(
+            PeerId::Private(0),
+            [
+                (TxId(0), (TxId(1), 3.0)),
+                (TxId(2), (TxId(3), 3.5)),
+                (TxId(4), (TxId(6), 7.0)),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+
+        let stats = ConflictStats::new(&conflicts, created, &versions);
+        assert_eq!(stats.num_conflicts, 3);
+        assert_eq!(stats.split_times, vec![1.0, 2.0]);
+        assert_eq!(stats.unsettled, 1);
+        assert_eq!(stats.mean_split_time(), 1.5);
+        assert_eq!(stats.max_split_time(), 2.0);
+    }
+}
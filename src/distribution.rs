@@ -0,0 +1,140 @@
+//! Random distributions of configurable simulation parameters.
+
+use rand::distributions::{Distribution, Exp};
+use rand::Rng;
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Distribution of a non-negative value, such as a session length in seconds.
+///
+/// Parses from `fixed:<v>`, `uniform:<min>-<max>` or `exp:<mean>`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ValueDistribution {
+    Fixed(f64),
+    Uniform(f64, f64),
+    Exponential(f64),
+}
+
+impl ValueDistribution {
+    /// Draws a single value.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> f64 {
+        match *self {
+            ValueDistribution::Fixed(v) => v,
+            ValueDistribution::Uniform(min, max) => rng.gen_range(min, max),
+            ValueDistribution::Exponential(mean) => Exp::new(1.0 / mean).sample(rng),
+        }
+    }
+
+    /// Draws a single value as a duration in seconds.
+    pub fn sample_duration<R: Rng>(&self, rng: &mut R) -> Duration {
+        Duration::from_secs_f64(self.sample(rng).max(0.0))
+    }
+
+    /// Expected value of the distribution.
+    pub fn mean(&self) -> f64 {
+        match *self {
+            ValueDistribution::Fixed(v) => v,
+            ValueDistribution::Uniform(min, max) => (min + max) / 2.0,
+            ValueDistribution::Exponential(mean) => mean,
+        }
+    }
+}
+
+/// Parses a share of events, such as a probability, between 0 and 1.
+pub fn parse_share(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|v| (0.0..=1.0).contains(v))
+        .ok_or_else(|| format!("invalid share: {}", s))
+}
+
+impl Display for ValueDistribution {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            ValueDistribution::Fixed(v) => write!(f, "fixed:{}", v),
+            ValueDistribution::Uniform(min, max) => write!(f, "uniform:{}-{}", min, max),
+            ValueDistribution::Exponential(mean) => write!(f, "exp:{}", mean),
+        }
+    }
+}
+
+impl FromStr for ValueDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| {
+            v.parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .ok_or_else(|| format!("invalid value in distribution: {}", s))
+        };
+
+        let (kind, params) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <kind>:<params>, got: {}", s))?;
+
+        match kind {
+            "fixed" => Ok(ValueDistribution::Fixed(parse(params)?)),
+            "uniform" => {
+                let (min, max) = params
+                    .split_once('-')
+                    .ok_or_else(|| format!("expected uniform:<min>-<max>, got: {}", s))?;
+                let (min, max) = (parse(min)?, parse(max)?);
+                if min >= max {
+                    return Err(format!("empty uniform range: {}", s));
+                }
+
+                Ok(ValueDistribution::Uniform(min, max))
+            }
+            "exp" => {
+                let mean = parse(params)?;
+                if mean <= 0.0 {
+                    return Err(format!("exponential mean must be positive: {}", s));
+                }
+
+                Ok(ValueDistribution::Exponential(mean))
+            }
+            _ => Err(format!("unknown distribution: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ValueDistribution;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    pub fn test_parse_distributions() {
+        assert_eq!("fixed:10".parse(), Ok(ValueDistribution::Fixed(10.0)));
+        assert_eq!(
+            "uniform:0.5-2".parse(),
+            Ok(ValueDistribution::Uniform(0.5, 2.0))
+        );
+        assert_eq!("exp:30".parse(), Ok(ValueDistribution::Exponential(30.0)));
+
+        for invalid in ["", "exp", "exp:0", "uniform:3-1", "fixed:-1", "normal:1"].iter() {
+            assert!(invalid.parse::<ValueDistribution>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    pub fn test_sample_mean() {
+        let mut rng = XorShiftRng::from_seed([7u8; 16]);
+
+        for dist in [
+            ValueDistribution::Fixed(5.0),
+            ValueDistribution::Uniform(2.0, 8.0),
+            ValueDistribution::Exponential(5.0),
+        ]
+        .iter()
+        {
+            let n = 20_000;
+            let mean = (0..n).map(|_| dist.sample(&mut rng)).sum::<f64>() / n as f64;
+            assert!((mean - dist.mean()).abs() < 0.2, "{}: {}", dist, mean);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Eclipse attack: an attacker controls a share of public peers and most of the
+//! outbound connections of victim private peers.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+
+use crate::peer::PeerId;
+
+/// Describes an eclipse attack scenario.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EclipseConfig {
+    /// Share of public peers the attacker controls.
+    pub attacker_share: f64,
+
+    /// Private peers the attacker tries to cut off the network.
+    pub victims: BTreeSet<PeerId>,
+
+    /// Number of public peers each victim connects to. Victims connect to attacker's
+    /// peers first, and fill the rest of the slots with honest ones.
+    pub victim_outbound: usize,
+}
+
+impl EclipseConfig {
+    /// Public peers victim connects to, given the attacker's and the honest public peers.
+    pub fn victim_peers(&self, attackers: &[PeerId], honest: &[PeerId]) -> Vec<PeerId> {
+        attackers
+            .iter()
+            .chain(honest.iter())
+            .take(self.victim_outbound)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Whether transactions made it into and out of the eclipsed victims.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct EclipseStats {
+    pub num_victims: usize,
+    pub num_attackers: usize,
+
+    /// Average share of victims' transactions that honest peers received.
+    pub victim_reach: f64,
+
+    /// Average share of other peers' transactions that victims received.
+    pub victim_coverage: f64,
+}
+
+impl EclipseStats {
+    /// Computes the stats from the number of transactions of each origin known by each
+    /// peer (`known_origins`), and the number of transactions created by each peer.
+    pub fn new(
+        victims: &BTreeSet<PeerId>,
+        num_attackers: usize,
+        honest: impl Iterator<Item = PeerId>,
+        known_origins: &HashMap<PeerId, HashMap<PeerId, u32>>,
+        txs_by_origin: &HashMap<PeerId, usize>,
+    ) -> Self {
+        let victim_txs = victims
+            .iter()
+            .map(|id| txs_by_origin.get(id).cloned().unwrap_or(0))
+            .sum::<usize>();
+        let other_txs = txs_by_origin.values().sum::<usize>() - victim_txs;
+
+        // Number of transactions `peer` knows from origins `victims` (or others)
+        let known = |peer: &PeerId, from_victims: bool| {
+            known_origins
+                .get(peer)
+                .map(|origins| {
+                    origins
+                        .iter()
+                        .filter(|(origin, _)| victims.contains(origin) == from_victims)
+                        .map(|(_, count)| *count as usize)
+                        .sum::<usize>()
+                })
+                .unwrap_or(0)
+        };
+
+        let share = |known: usize, total: usize| {
+            if total == 0 {
+                0.0
+            } else {
+                known as f64 / total as f64
+            }
+        };
+
+        let honest = honest
+            .filter(|id| !victims.contains(id))
+            .collect::<Vec<_>>();
+        let reached = honest.iter().map(|id| known(id, true)).sum();
+        let covered = victims.iter().map(|id| known(id, false)).sum();
+
+        EclipseStats {
+            num_victims: victims.len(),
+            num_attackers,
+            victim_reach: share(reached, victim_txs * honest.len()),
+            victim_coverage: share(covered, other_txs * victims.len()),
+        }
+    }
+}
+
+impl Display for EclipseStats {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{} attackers, {} victims: victims' transactions reached {:.2}% of honest peers, \
+             victims received {:.2}% of others' transactions",
+            self.num_attackers,
+            self.num_victims,
+            self.victim_reach * 100.0,
+            self.victim_coverage * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EclipseConfig, EclipseStats};
+    use crate::peer::PeerId;
+    use std::collections::HashMap;
+
+    #[test]
+    pub fn test_eclipse_stats() {
+        let (victim, honest) = (PeerId::Private(0), PeerId::Private(1));
+        let config = EclipseConfig {
+            attacker_share: 0.5,
+            victims: [victim].iter().cloned().collect(),
+            victim_outbound: 2,
+        };
+
+        let (attackers, others) = (
+            [PeerId::Public(1), PeerId::Public(2)],
+            [PeerId::Public(0), PeerId::Public(3)],
+        );
+        assert_eq!(config.victim_peers(&attackers, &others), attackers.to_vec());
+
+        let txs_by_origin = [(victim, 2), (honest, 4)].iter().cloned().collect();
+        let mut known_origins = HashMap::new();
+        known_origins.insert(victim, [(victim, 2), (honest, 1)].iter().cloned().collect());
+        known_origins.insert(honest, [(honest, 4)].iter().cloned().collect());
+        known_origins.insert(
+            PeerId::Public(0),
+            [(victim, 1), (honest, 4)].iter().cloned().collect(),
+        );
+
+        let stats = EclipseStats::new(
+            &config.victims,
+            attackers.len(),
+            [victim, honest, PeerId::Public(0)].iter().cloned(),
+            &known_origins,
+            &txs_by_origin,
+        );
+
+        assert_eq!(stats.victim_reach, 0.25);
+        assert_eq!(stats.victim_coverage, 0.25);
+    }
+}
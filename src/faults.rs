@@ -0,0 +1,221 @@
+//! Fault injection: links and network partitions that fail for a period of simulated time.
+//!
+//! Messages sent over a failed link are dropped and counted by the sending peer.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::peer::PeerId;
+
+/// Failure of a part of the network.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Cuts the link between two peers.
+    CutLink(PeerId, PeerId),
+
+    /// Splits the network into groups, so peers of different groups can't reach each other.
+    /// Peers that aren't listed in any group form one more group together.
+    Partition(Vec<HashSet<PeerId>>),
+}
+
+impl Fault {
+    /// Whether this fault cuts the link between peers `a` and `b`.
+    pub fn cuts(&self, a: PeerId, b: PeerId) -> bool {
+        match self {
+            Fault::CutLink(x, y) => (*x == a && *y == b) || (*x == b && *y == a),
+            Fault::Partition(groups) => {
+                let group_of = |id: PeerId| groups.iter().position(|group| group.contains(&id));
+                group_of(a) != group_of(b)
+            }
+        }
+    }
+}
+
+/// A fault that starts at a given simulated time and is healed later, if ever.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledFault {
+    pub fault: Fault,
+    pub start: Duration,
+    pub end: Option<Duration>,
+}
+
+impl ScheduledFault {
+    /// Whether the fault is in effect at simulated time `now`.
+    pub fn is_active(&self, now: Duration) -> bool {
+        now >= self.start && self.end.is_none_or(|end| now < end)
+    }
+}
+
+/// Parses `<peers>@<start>..[<end>]`, where times are in seconds.
+fn parse_schedule(s: &str) -> Result<(&str, Duration, Option<Duration>), String> {
+    let (peers, times) = s
+        .rsplit_once('@')
+        .ok_or_else(|| format!("expected <peers>@<start>..[<end>], got: {}", s))?;
+    let (start, end) = times
+        .split_once("..")
+        .ok_or_else(|| format!("expected <start>..[<end>], got: {}", times))?;
+
+    let parse_secs = |v: &str| {
+        v.parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite() && *v >= 0.0)
+            .map(Duration::from_secs_f64)
+            .ok_or_else(|| format!("invalid time: {}", v))
+    };
+
+    let start = parse_secs(start)?;
+    let end = if end.is_empty() {
+        None
+    } else {
+        Some(parse_secs(end)?)
+    };
+
+    if end.is_some_and(|end| end <= start) {
+        return Err(format!("fault heals before it starts: {}", s));
+    }
+
+    Ok((peers, start, end))
+}
+
+fn parse_peers(s: &str, separator: char) -> Result<Vec<PeerId>, String> {
+    s.split(separator).map(str::parse).collect()
+}
+
+/// Parses a link cut given as `<peer>-<peer>@<start>..[<end>]`, e.g. `pub0-pub1@10..60`.
+pub fn parse_cut_link(s: &str) -> Result<ScheduledFault, String> {
+    let (peers, start, end) = parse_schedule(s)?;
+    match parse_peers(peers, '-')?.as_slice() {
+        [a, b] => Ok(ScheduledFault {
+            fault: Fault::CutLink(*a, *b),
+            start,
+            end,
+        }),
+        _ => Err(format!("expected exactly two peers: {}", peers)),
+    }
+}
+
+/// Parses a partition given as `<group>|<group>...@<start>..[<end>]`,
+/// where groups are comma-separated peers, e.g. `pub0,priv0|pub1@10..60`.
+pub fn parse_partition(s: &str) -> Result<ScheduledFault, String> {
+    let (groups, start, end) = parse_schedule(s)?;
+    let groups = groups
+        .split('|')
+        .map(|group| parse_peers(group, ',').map(|peers| peers.into_iter().collect()))
+        .collect::<Result<Vec<HashSet<_>>, _>>()?;
+
+    Ok(ScheduledFault {
+        fault: Fault::Partition(groups),
+        start,
+        end,
+    })
+}
+
+/// All faults injected during a simulation.
+#[derive(Debug, Clone, Default)]
+pub struct FaultSchedule {
+    faults: Vec<ScheduledFault>,
+}
+
+impl FaultSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, fault: ScheduledFault) {
+        self.faults.push(fault);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.faults.is_empty()
+    }
+
+    /// Whether the link between `a` and `b` is cut at simulated time `now`.
+    pub fn is_cut(&self, a: PeerId, b: PeerId, now: Duration) -> bool {
+        self.faults
+            .iter()
+            .any(|fault| fault.is_active(now) && fault.fault.cuts(a, b))
+    }
+}
+
+impl Display for ScheduledFault {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match &self.fault {
+            Fault::CutLink(a, b) => write!(f, "{:?}-{:?}", a, b)?,
+            Fault::Partition(groups) => {
+                let groups = groups
+                    .iter()
+                    .map(|group| {
+                        let mut group = group.iter().collect::<Vec<_>>();
+                        group.sort_by_key(|id| u64::from(**id));
+                        group
+                            .iter()
+                            .map(|id| format!("{:?}", id))
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", groups.join("|"))?
+            }
+        }
+
+        write!(f, "@{}..", self.start.as_secs_f64())?;
+        if let Some(end) = self.end {
+            write!(f, "{}", end.as_secs_f64())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_cut_link, parse_partition, FaultSchedule};
+    use crate::peer::PeerId;
+    use std::time::Duration;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    pub fn test_cut_link() {
+        let mut schedule = FaultSchedule::new();
+        schedule.add(parse_cut_link("pub0-priv3@10..20").expect("Parse cut link"));
+
+        let (a, b, c) = (PeerId::Public(0), PeerId::Private(3), PeerId::Public(1));
+
+        assert!(!schedule.is_cut(a, b, secs(5)));
+        assert!(schedule.is_cut(a, b, secs(10)));
+        assert!(schedule.is_cut(b, a, secs(15)));
+        assert!(!schedule.is_cut(a, c, secs(15)));
+        assert!(!schedule.is_cut(a, b, secs(20)));
+    }
+
+    #[test]
+    pub fn test_partition() {
+        let mut schedule = FaultSchedule::new();
+        let partition = parse_partition("pub0,priv0|pub1@0..").expect("Parse partition");
+        assert_eq!(partition.to_string(), "pub0,priv0|pub1@0..");
+        schedule.add(partition);
+
+        let (pub0, pub1, pub2) = (PeerId::Public(0), PeerId::Public(1), PeerId::Public(2));
+        let (priv0, priv1) = (PeerId::Private(0), PeerId::Private(1));
+
+        assert!(!schedule.is_cut(pub0, priv0, secs(100)));
+        assert!(schedule.is_cut(pub0, pub1, secs(100)));
+        assert!(schedule.is_cut(priv0, pub1, secs(100)));
+        // Unlisted peers form a group of their own
+        assert!(!schedule.is_cut(pub2, priv1, secs(100)));
+        assert!(schedule.is_cut(pub2, pub1, secs(100)));
+    }
+
+    #[test]
+    pub fn test_parse_errors() {
+        for invalid in ["pub0-pub1", "pub0@1..2", "pub0-pub1@5..1", "pub0-peer1@0.."].iter() {
+            assert!(parse_cut_link(invalid).is_err(), "{}", invalid);
+        }
+
+        assert!(parse_partition("pub0|@0..").is_err());
+    }
+}
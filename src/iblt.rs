@@ -0,0 +1,295 @@
+//! Invertible Bloom Lookup Table: a set sketch of cells that sum up elements hashed to them.
+//!
+//! Cells are summed up with XOR, so merging two IBLTs yields an IBLT of the symmetric
+//! difference of their sets. It decodes by peeling cells that hold a single element.
+//! Peeling is likely to succeed for differences well below capacity, but less so
+//! at full capacity, especially in small IBLTs: one of capacity 2 never decodes
+//! 2 elements, since both of them land in the same 3 cells.
+
+use siphasher::sip::SipHasher;
+use std::hash::Hasher;
+
+use crate::recset::RecSetError;
+use crate::sketch::SetSketch;
+
+/// Number of cells each element is added to, one in each subtable.
+const HASHES: usize = 3;
+
+/// Cells per element of capacity. Peeling with 3 hashes starts to fail below ~1.23.
+const CELLS_PER_ELEMENT: f64 = 1.5;
+
+/// Size of a cell checksum in bytes.
+const CHECKSUM_BYTES: usize = 4;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct Cell {
+    /// XOR of elements in the cell.
+    id_sum: u64,
+
+    /// XOR of checksums of elements in the cell.
+    hash_sum: u32,
+}
+
+impl Cell {
+    fn toggle(&mut self, element: u64, checksum: u32) {
+        self.id_sum ^= element;
+        self.hash_sum ^= checksum;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.id_sum == 0 && self.hash_sum == 0
+    }
+}
+
+/// IBLT of a set of non-zero `bits`-wide elements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Iblt {
+    bits: u32,
+    capacity: usize,
+    seed: u64,
+    cells: Vec<Cell>,
+}
+
+impl Iblt {
+    /// Creates an empty IBLT sized for differences of up to `capacity` elements
+    /// of `bits` bits, from 2 to 64.
+    pub fn new(bits: u32, capacity: usize, seed: Option<u64>) -> Self {
+        assert!(
+            (2..=64).contains(&bits),
+            "Unsupported element size: {}",
+            bits
+        );
+
+        let cells_per_hash = (capacity as f64 * CELLS_PER_ELEMENT / HASHES as f64).ceil() as usize;
+
+        Iblt {
+            bits,
+            capacity,
+            seed: seed.unwrap_or(0),
+            cells: vec![Cell::default(); cells_per_hash.max(1) * HASHES],
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn hash(&self, element: u64, tag: u64) -> u64 {
+        let mut hasher = SipHasher::new_with_keys(self.seed, tag);
+        hasher.write_u64(element);
+        hasher.finish()
+    }
+
+    fn checksum(&self, element: u64) -> u32 {
+        self.hash(element, u64::from(u32::MAX)) as u32
+    }
+
+    /// Cells `element` is added to, one in each subtable.
+    fn cell_indices(&self, element: u64) -> [usize; HASHES] {
+        let per_hash = self.cells.len() / HASHES;
+        let mut indices = [0; HASHES];
+        for (i, index) in indices.iter_mut().enumerate() {
+            *index = i * per_hash + (self.hash(element, i as u64) % per_hash as u64) as usize;
+        }
+
+        indices
+    }
+
+    fn id_bytes(&self) -> usize {
+        (self.bits as usize).div_ceil(8)
+    }
+}
+
+impl SetSketch for Iblt {
+    fn add(&mut self, element: u64) {
+        let element = element & (u64::MAX >> (64 - self.bits));
+        let checksum = self.checksum(element);
+        for index in self.cell_indices(element).iter() {
+            self.cells[*index].toggle(element, checksum);
+        }
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.cells.len() * (self.id_bytes() + CHECKSUM_BYTES)
+    }
+
+    fn serialize(&self, buf: &mut [u8]) -> Result<(), RecSetError> {
+        if buf.len() < self.serialized_size() {
+            return Err(RecSetError::InvalidSketch);
+        }
+
+        let id_bytes = self.id_bytes();
+        let chunks = buf.chunks_mut(id_bytes + CHECKSUM_BYTES);
+        for (cell, chunk) in self.cells.iter().zip(chunks) {
+            chunk[..id_bytes].copy_from_slice(&cell.id_sum.to_le_bytes()[..id_bytes]);
+            chunk[id_bytes..].copy_from_slice(&cell.hash_sum.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    fn deserialize(&mut self, buf: &[u8]) {
+        let id_bytes = self.id_bytes();
+        let chunks = buf.chunks(id_bytes + CHECKSUM_BYTES);
+        for (cell, chunk) in self.cells.iter_mut().zip(chunks) {
+            let mut id_sum = [0u8; 8];
+            let mut hash_sum = [0u8; CHECKSUM_BYTES];
+            let (id, hash) = chunk.split_at(id_bytes.min(chunk.len()));
+            id_sum[..id.len()].copy_from_slice(id);
+            hash_sum[..hash.len()].copy_from_slice(hash);
+
+            cell.id_sum = u64::from_le_bytes(id_sum);
+            cell.hash_sum = u32::from_le_bytes(hash_sum);
+        }
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<(), RecSetError> {
+        if self.bits != other.bits || self.cells.len() != other.cells.len() {
+            return Err(RecSetError::InvalidSketch);
+        }
+
+        for (a, b) in self.cells.iter_mut().zip(other.cells.iter()) {
+            a.toggle(b.id_sum, b.hash_sum);
+        }
+
+        Ok(())
+    }
+
+    fn decode(&self, elements: &mut [u64]) -> Result<usize, RecSetError> {
+        let mut iblt = self.clone();
+        let mut num = 0;
+
+        // A cell is pure if it holds a single element, which must hash to it
+        let is_pure = |iblt: &Iblt, index: usize| {
+            let cell = iblt.cells[index];
+            cell.id_sum != 0
+                && cell.hash_sum == iblt.checksum(cell.id_sum)
+                && iblt.cell_indices(cell.id_sum).contains(&index)
+        };
+
+        let mut pending = (0..iblt.cells.len()).collect::<Vec<_>>();
+        while let Some(index) = pending.pop() {
+            if !is_pure(&iblt, index) {
+                continue;
+            }
+
+            if num == elements.len() {
+                return Err(RecSetError::DecodeFailed);
+            }
+
+            let element = iblt.cells[index].id_sum;
+            elements[num] = element;
+            num += 1;
+
+            // Removing the element may leave other cells with a single element
+            iblt.add(element);
+            pending.extend(iblt.cell_indices(element).iter());
+        }
+
+        if iblt.cells.iter().all(Cell::is_empty) {
+            Ok(num)
+        } else {
+            Err(RecSetError::DecodeFailed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Iblt;
+    use crate::recset::RecSetError;
+    use crate::sketch::SetSketch;
+
+    #[test]
+    pub fn test_iblt_decode() {
+        for bits in [12u32, 32, 64].iter().cloned() {
+            let mask = u64::MAX >> (64 - bits);
+            let capacity = 20;
+            let mut a = Iblt::new(bits, capacity, Some(7));
+            let mut b = Iblt::new(bits, capacity, Some(7));
+            let mut expected = vec![];
+            for i in 1..=200u64 {
+                let element = i.wrapping_mul(0x9e37_79b9_7f4a_7c15) & mask;
+                if i % 20 == 0 {
+                    a.add(element);
+                    expected.push(element);
+                } else {
+                    a.add(element);
+                    b.add(element);
+                }
+            }
+
+            let mut buf = vec![0u8; b.serialized_size()];
+            b.serialize(&mut buf).expect("Serialize");
+            let mut received = Iblt::new(bits, capacity, Some(7));
+            received.deserialize(&buf);
+            assert_eq!(received, b);
+
+            a.merge(&received).expect("Merge");
+            let mut elements = vec![0u64; capacity];
+            let num = a.decode(&mut elements).expect("Decode");
+            let mut decoded = elements[..num].to_vec();
+            decoded.sort();
+            expected.sort();
+            assert_eq!(decoded, expected, "{} bits", bits);
+        }
+    }
+
+    /// Share of differences of `size` elements an IBLT of `capacity` decodes,
+    /// out of 200 random ones.
+    fn decode_rate(capacity: usize, size: usize) -> f64 {
+        let decoded = (0..200u64)
+            .filter(|seed| {
+                let mut iblt = Iblt::new(32, capacity, Some(*seed));
+                for i in 1..=size as u64 {
+                    iblt.add(i.wrapping_mul(0x9e37_79b9) ^ (seed << 8));
+                }
+
+                let mut elements = vec![0u64; capacity];
+                iblt.decode(&mut elements) == Ok(size)
+            })
+            .count();
+
+        decoded as f64 / 200.0
+    }
+
+    #[test]
+    pub fn test_iblt_full_capacity() {
+        assert!(decode_rate(16, 8) > 0.9);
+        assert!(decode_rate(128, 64) > 0.95);
+
+        // Large IBLTs decode most full-capacity differences, small ones fail often
+        assert!(decode_rate(128, 128) > 0.9);
+        let rate = decode_rate(16, 16);
+        assert!(rate > 0.4 && rate < 0.8, "{}", rate);
+    }
+
+    #[test]
+    pub fn test_iblt_small_capacity() {
+        for capacity in 1..=3 {
+            assert_eq!(decode_rate(capacity, 1), 1.0, "capacity {}", capacity);
+        }
+
+        // Each element is in all 3 cells of an IBLT of capacity 2, so none is alone in one
+        assert_eq!(decode_rate(2, 2), 0.0);
+        assert!(decode_rate(3, 3) > 0.5);
+    }
+
+    #[test]
+    pub fn test_iblt_overfull() {
+        let mut iblt = Iblt::new(32, 4, None);
+        for element in 1..=40 {
+            iblt.add(element * 1_000_003);
+        }
+
+        let mut elements = vec![0u64; 4];
+        assert_eq!(iblt.decode(&mut elements), Err(RecSetError::DecodeFailed));
+        assert!(Iblt::new(32, 4, None)
+            .merge(&Iblt::new(32, 8, None))
+            .is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! An [Actix]-based simulator for evaluation of [Erlay] transaction propagation technique.
+//!
+//! Simulations are described with [`SimulationBuilder`] and produce [`SimulationResults`]:
+//!
+//! ```no_run
+//! use actix_elray_sim::{RelayKind, SimulationBuilder};
+//!
+//! let results = SimulationBuilder::new()
+//!     .num_private_nodes(8)
+//!     .num_public_nodes(2)
+//!     .relay(RelayKind::Erlay)
+//!     .run()
+//!     .expect("Simulation results");
+//!
+//! println!("{}", results.total_traffic());
+//! ```
+//!
+//! [Actix]: https://github.com/actix/actix
+//! [Erlay]: https://arxiv.org/pdf/1905.10518.pdf
+//! [`SimulationBuilder`]: simulation/struct.SimulationBuilder.html
+//! [`SimulationResults`]: simulation/struct.SimulationResults.html
+
+pub mod adversary;
+pub mod block;
+pub mod churn;
+pub mod clock;
+pub mod compact_block;
+pub mod conflicts;
+pub mod distribution;
+pub mod eclipse;
+pub mod faults;
+pub mod iblt;
+pub mod link;
+pub mod mempool;
+pub mod messages;
+pub mod orphans;
+pub mod peer;
+pub mod pinsketch;
+pub mod recset;
+pub mod relay;
+pub mod short_ids;
+pub mod simulation;
+pub mod sketch;
+pub mod spy;
+pub mod trace;
+pub mod traffic_counter;
+pub mod tx_registry;
+
+pub use crate::adversary::{Misbehaviour, Role};
+pub use crate::block::{Block, BlockId, MiningConfig};
+pub use crate::churn::ChurnConfig;
+pub use crate::clock::Clock;
+pub use crate::conflicts::ConflictStats;
+pub use crate::distribution::ValueDistribution;
+pub use crate::eclipse::{EclipseConfig, EclipseStats};
+pub use crate::faults::{Fault, FaultSchedule, ScheduledFault};
+pub use crate::iblt::Iblt;
+pub use crate::link::{LinkModel, NetworkModel};
+pub use crate::mempool::{Inserted, Mempool, MempoolConfig, MempoolError};
+pub use crate::messages::{
+    BlockTxn, CompactBlock, Connect, Delayed, Disconnect, DoubleSpend, FeeFilter, GetBlockTxn,
+    GetTxs, Inv, PeerBlock, PeerTx, ReconcileRequest, ReconcileResult, Traffic, TrafficReport, Tx,
+    Txs,
+};
+pub use crate::orphans::OrphanPool;
+pub use crate::peer::{Peer, PeerConfig, PeerId};
+pub use crate::pinsketch::PinSketch;
+pub use crate::recset::{RecSet, SetDifference, ShortId};
+pub use crate::relay::{RelayKind, RelayStrategy};
+pub use crate::short_ids::{ShortIdKey, ShortIds};
+pub use crate::simulation::{SimulationBuilder, SimulationError, SimulationResults};
+pub use crate::sketch::{SetSketch, SketchKind};
+pub use crate::spy::{SpyEstimate, SpyLog};
+pub use crate::trace::{Mechanism, TraceEvent, TxTrace};
+pub use crate::traffic_counter::TrafficData;
+pub use crate::tx_registry::{TxId, TxRegistry};
+
+/// Delay before peers start reconciling their sets, in seconds.
+pub const RECONCIL_TIMEOUT_SEC: u64 = 1;
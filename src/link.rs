@@ -0,0 +1,194 @@
+//! Unreliable links: random message loss and bounded reordering.
+
+use rand::Rng;
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::peer::PeerId;
+
+/// Quality of a link between two peers.
+///
+/// Parses from comma-separated `loss=<probability>` and `delay=<secs>`, e.g. `loss=0.1,delay=0.5`.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct LinkModel {
+    /// Probability that a message is lost.
+    pub loss: f64,
+
+    /// Upper bound of a random delay added to every message, in simulated time.
+    /// Messages with different delays overtake each other.
+    pub max_delay: Duration,
+}
+
+/// Fate of a single message sent over a link.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Delivery {
+    Lost,
+    Delayed(Duration),
+}
+
+impl LinkModel {
+    /// Whether the link delivers every message immediately and in order.
+    pub fn is_reliable(&self) -> bool {
+        self.loss == 0.0 && self.max_delay == Duration::from_secs(0)
+    }
+
+    /// Decides what happens to a message sent over this link.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Delivery {
+        if self.loss > 0.0 && rng.gen_bool(self.loss) {
+            return Delivery::Lost;
+        }
+
+        if self.max_delay == Duration::from_secs(0) {
+            Delivery::Delayed(self.max_delay)
+        } else {
+            Delivery::Delayed(self.max_delay.mul_f64(rng.gen_range(0.0, 1.0)))
+        }
+    }
+}
+
+impl Display for LinkModel {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "loss={},delay={}",
+            self.loss,
+            self.max_delay.as_secs_f64()
+        )
+    }
+}
+
+impl FromStr for LinkModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut model = LinkModel::default();
+
+        for param in s.split(',') {
+            let (name, value) = param
+                .split_once('=')
+                .ok_or_else(|| format!("expected <name>=<value>, got: {}", param))?;
+            let value = value
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .ok_or_else(|| format!("invalid value: {}", param))?;
+
+            match name {
+                "loss" if value <= 1.0 => model.loss = value,
+                "delay" => model.max_delay = Duration::from_secs_f64(value),
+                _ => return Err(format!("invalid link parameter: {}", param)),
+            }
+        }
+
+        Ok(model)
+    }
+}
+
+/// Parses a model of a single link given as `<peer>-<peer>:<model>`, e.g. `pub0-priv1:loss=0.3`.
+pub fn parse_link(s: &str) -> Result<(PeerId, PeerId, LinkModel), String> {
+    let (peers, model) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected <peer>-<peer>:<model>, got: {}", s))?;
+    let (a, b) = peers
+        .split_once('-')
+        .ok_or_else(|| format!("expected <peer>-<peer>, got: {}", peers))?;
+
+    Ok((a.parse()?, b.parse()?, model.parse()?))
+}
+
+/// Models of all links in the network.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkModel {
+    /// Model of links that aren't configured individually.
+    pub default: LinkModel,
+
+    links: HashMap<(PeerId, PeerId), LinkModel>,
+}
+
+impl NetworkModel {
+    pub fn new(default: LinkModel) -> Self {
+        NetworkModel {
+            default,
+            links: HashMap::new(),
+        }
+    }
+
+    /// Overrides the model of the link between `a` and `b`, in both directions.
+    pub fn set_link(&mut self, a: PeerId, b: PeerId, model: LinkModel) {
+        self.links.insert(Self::key(a, b), model);
+    }
+
+    /// Model of the link between `a` and `b`.
+    pub fn link(&self, a: PeerId, b: PeerId) -> &LinkModel {
+        self.links.get(&Self::key(a, b)).unwrap_or(&self.default)
+    }
+
+    fn key(a: PeerId, b: PeerId) -> (PeerId, PeerId) {
+        if u64::from(a) <= u64::from(b) {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_link, Delivery, LinkModel, NetworkModel};
+    use crate::peer::PeerId;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_parse_links() {
+        let model = "loss=0.25,delay=0.5"
+            .parse::<LinkModel>()
+            .expect("Parse link");
+        assert_eq!(model.loss, 0.25);
+        assert_eq!(model.max_delay, Duration::from_millis(500));
+        assert_eq!(model.to_string(), "loss=0.25,delay=0.5");
+
+        let (a, b, model) = parse_link("pub0-priv1:loss=1").expect("Parse link override");
+        let mut network = NetworkModel::default();
+        network.set_link(a, b, model);
+        assert_eq!(
+            network.link(PeerId::Private(1), PeerId::Public(0)).loss,
+            1.0
+        );
+        assert!(network
+            .link(PeerId::Public(0), PeerId::Public(1))
+            .is_reliable());
+
+        for invalid in ["loss=2", "loss=-1", "jitter=1", "loss"].iter() {
+            assert!(invalid.parse::<LinkModel>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    pub fn test_sample_delivery() {
+        let mut rng = XorShiftRng::from_seed([7u8; 16]);
+        let model = LinkModel {
+            loss: 0.2,
+            max_delay: Duration::from_secs(1),
+        };
+
+        let n = 10_000;
+        let mut lost = 0;
+        for _ in 0..n {
+            match model.sample(&mut rng) {
+                Delivery::Lost => lost += 1,
+                Delivery::Delayed(delay) => assert!(delay < model.max_delay),
+            }
+        }
+
+        assert!(
+            (lost as f64 / n as f64 - model.loss).abs() < 0.02,
+            "{}",
+            lost
+        );
+    }
+}
@@ -1,28 +1,37 @@
-mod messages;
-mod peer;
-mod recset;
-mod traffic_counter;
-
-use crate::messages::Connect;
-use crate::peer::PeerId;
-
-use actix::prelude::*;
-
-use crate::traffic_counter::TrafficCounter;
+use actix_elray_sim::adversary::{parse_peers, parse_role, parse_role_fraction};
+use actix_elray_sim::clock::{parse_seconds, parse_time_scale};
+use actix_elray_sim::distribution::parse_share;
+use actix_elray_sim::faults::{parse_cut_link, parse_partition};
+use actix_elray_sim::link::parse_link;
+use actix_elray_sim::recset::parse_short_id_bits;
+use actix_elray_sim::simulation::{DEFAULT_MAX_RETRIES, DEFAULT_REQUEST_TIMEOUT_SEC};
+use actix_elray_sim::sketch::parse_capacity;
+use actix_elray_sim::trace::write_jsonl;
+use actix_elray_sim::{
+    ChurnConfig, EclipseConfig, LinkModel, MempoolConfig, MiningConfig, PeerId, RelayKind, Role,
+    ScheduledFault, SimulationBuilder, SketchKind, ValueDistribution,
+};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::*;
 
-pub const RECONCIL_TIMEOUT_SEC: u64 = 1;
-
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "simulator",
     about = "An Erlay transaction propagation technique simulator"
 )]
 struct SimulatorParameters {
-    /// Use reconciliation (Erlay)
+    /// Use reconciliation (Erlay), same as `--relay=erlay`
     #[structopt(short = "r", long = "reconciliation")]
     pub use_reconciliation: bool,
 
+    /// Relay strategy: flooding or erlay.
+    #[structopt(long = "relay", default_value = "flooding")]
+    pub relay: RelayKind,
+
     /// Number of private nodes that doesn't have inbound connections.
     #[structopt(long = "numprivate", default_value = "8")]
     pub num_private_nodes: u32,
@@ -31,76 +40,294 @@ struct SimulatorParameters {
     #[structopt(long = "numpublic", default_value = "2")]
     pub num_public_nodes: u32,
 
+    /// Number of spy nodes that connect like public nodes and guess transaction origins.
+    #[structopt(long = "spies", default_value = "0")]
+    pub num_spies: u32,
+
     /// Seed for a random number generator.
     #[structopt(short = "s", long = "seed")]
     pub seed: Option<u64>,
-}
 
-fn estimate_traffic_timeout_sec(parameters: &SimulatorParameters) -> u64 {
-    (parameters.num_private_nodes as f32 * 0.57f32 + parameters.num_public_nodes as f32 * 1f32)
-        as u64
+    /// How many times faster than the wall clock simulated time runs.
+    #[structopt(
+        long = "time-scale",
+        default_value = "1",
+        parse(try_from_str = "parse_time_scale")
+    )]
+    pub time_scale: f64,
+
+    /// Simulated duration in seconds. Estimated from the network size by default.
+    #[structopt(long = "duration")]
+    pub duration: Option<u64>,
+
+    /// Interval between transactions of each private node in seconds, e.g. `exp:10`.
+    /// Every private node creates a single transaction by default.
+    #[structopt(long = "tx-interval")]
+    pub tx_interval: Option<ValueDistribution>,
+
+    /// Interval between reconciliation rounds in seconds. Peers reconcile once by default.
+    #[structopt(
+        long = "reconciliation-interval",
+        parse(try_from_str = "parse_seconds")
+    )]
+    pub reconciliation_interval: Option<Duration>,
+
+    /// Capacity of reconciliation sketches. Equals the number of private nodes by default.
+    #[structopt(long = "capacity", parse(try_from_str = "parse_capacity"))]
+    pub reconciliation_capacity: Option<usize>,
+
+    /// Online session length of nodes in seconds, e.g. `exp:60`. Enables churn.
+    #[structopt(long = "churn-session")]
+    pub churn_session: Option<ValueDistribution>,
+
+    /// Offline time of churning nodes in seconds.
+    #[structopt(long = "churn-downtime", default_value = "exp:20")]
+    pub churn_downtime: ValueDistribution,
+
+    /// Cut a link for a while, e.g. `pub0-priv1@10..60`. Open-ended if the end is omitted.
+    #[structopt(long = "cut-link", parse(try_from_str = "parse_cut_link"))]
+    pub cut_links: Vec<ScheduledFault>,
+
+    /// Split the network into groups for a while, e.g. `pub0,priv0|pub1@10..60`.
+    /// Unlisted nodes form one more group.
+    #[structopt(long = "partition", parse(try_from_str = "parse_partition"))]
+    pub partitions: Vec<ScheduledFault>,
+
+    /// Message loss and random delays of every link, e.g. `loss=0.05,delay=0.5`.
+    /// Delayed messages can overtake each other. Links are reliable by default.
+    #[structopt(long = "links", default_value = "loss=0,delay=0")]
+    pub links: LinkModel,
+
+    /// Message loss and random delays of a single link, e.g. `pub0-priv1:loss=0.3`.
+    #[structopt(long = "link", parse(try_from_str = "parse_link"))]
+    pub link_overrides: Vec<(PeerId, PeerId, LinkModel)>,
+
+    /// Time to wait for a response before sending a request again, in seconds.
+    #[structopt(long = "request-timeout", parse(try_from_str = "parse_seconds"))]
+    pub request_timeout: Option<Duration>,
+
+    /// Number of times an unanswered request is sent again.
+    #[structopt(long = "max-retries")]
+    pub max_retries: Option<u32>,
+
+    /// Width of short IDs in bits, from 2 to 64. Erlay uses 32-bit short IDs.
+    #[structopt(
+        long = "short-id-bits",
+        default_value = "64",
+        parse(try_from_str = "parse_short_id_bits")
+    )]
+    pub short_id_bits: u32,
+
+    /// Reconciliation sketches: minisketch, pinsketch or iblt. Minisketch by default
+    /// if it's compiled in.
+    #[structopt(long = "sketch")]
+    pub sketch: Option<SketchKind>,
+
+    /// Total size of transactions in each node's mempool at most, in bytes. The worst
+    /// paying transactions are evicted first. Unlimited by default.
+    #[structopt(long = "mempool-size")]
+    pub mempool_size: Option<u64>,
+
+    /// Time transactions stay in mempools at most, in seconds. Forever by default.
+    #[structopt(long = "mempool-expiry", parse(try_from_str = "parse_seconds"))]
+    pub mempool_expiry: Option<Duration>,
+
+    /// Nodes that mine blocks, e.g. `pub0,pub1`. No blocks are mined by default.
+    #[structopt(long = "miners", parse(try_from_str = "parse_peers"))]
+    pub miners: Option<BTreeSet<PeerId>>,
+
+    /// Mean time between blocks in seconds. Blocks are found on a Poisson schedule.
+    #[structopt(
+        long = "block-interval",
+        default_value = "600",
+        parse(try_from_str = "parse_seconds")
+    )]
+    pub block_interval: Duration,
+
+    /// Weight blocks have at most. Each byte of a transaction weighs 4.
+    #[structopt(long = "max-block-weight", default_value = "4000000")]
+    pub max_block_weight: u64,
+
+    /// Fee rates of transactions in satoshis per 1000 bytes, e.g. `exp:5000`.
+    /// Every transaction pays 1000 by default.
+    #[structopt(long = "fee-rate")]
+    pub fee_rate: Option<ValueDistribution>,
+
+    /// Minimum fee rate of each node's mempool in satoshis per 1000 bytes, e.g.
+    /// `uniform:0-3000`. Nodes announce it to each other with `feefilter`.
+    #[structopt(long = "min-fee-rate")]
+    pub min_fee_rate: Option<ValueDistribution>,
+
+    /// Share of transactions that spend the previous transaction of the same node,
+    /// if it's still unconfirmed, e.g. `0.3`. Transactions are independent by default.
+    #[structopt(long = "child-share", parse(try_from_str = "parse_share"))]
+    pub child_share: Option<f64>,
+
+    /// Number of orphan transactions each node keeps at most.
+    #[structopt(long = "max-orphans", default_value = "100")]
+    pub max_orphans: usize,
+
+    /// Share of transactions that replace the previous transaction of the same node,
+    /// if it's still unconfirmed, paying 1000 satoshis per 1000 bytes more, e.g. `0.2`.
+    #[structopt(long = "replace-share", parse(try_from_str = "parse_share"))]
+    pub replace_share: Option<f64>,
+
+    /// Share of transactions that a random node connected to the creator double-spends
+    /// at the same time, paying the same fee rate, e.g. `0.2`.
+    #[structopt(long = "double-spend-share", parse(try_from_str = "parse_share"))]
+    pub double_spend_share: Option<f64>,
+
+    /// Relay blocks as BIP152 compact blocks instead of in full.
+    #[structopt(long = "compact-blocks")]
+    pub compact_blocks: bool,
+
+    /// Use the same short IDs on all connections instead of salting them per connection.
+    #[structopt(long = "unsalted-short-ids")]
+    pub unsalted_short_ids: bool,
+
+    /// Make a node misbehave, e.g. `priv3=garbage-sketch`. Roles: garbage-sketch,
+    /// wrong-capacity-sketch, oversized-result, tx-request-spam, black-hole, free-rider,
+    /// colliding-tx and selective-relay:<nodes>, e.g. `selective-relay:priv0,priv1`.
+    #[structopt(long = "role", parse(try_from_str = "parse_role"))]
+    pub roles: Vec<(PeerId, Role)>,
+
+    /// Make a random share of nodes misbehave, e.g. `black-hole=0.2`.
+    #[structopt(long = "role-fraction", parse(try_from_str = "parse_role_fraction"))]
+    pub role_fractions: Vec<(Role, f64)>,
+
+    /// Private nodes to run an eclipse attack on, e.g. `priv0,priv1`.
+    #[structopt(long = "eclipse-victims", parse(try_from_str = "parse_peers"))]
+    pub eclipse_victims: Option<BTreeSet<PeerId>>,
+
+    /// Share of public nodes controlled by the eclipse attacker.
+    #[structopt(long = "eclipse-share", default_value = "0.5")]
+    pub eclipse_share: f64,
+
+    /// Number of public nodes each eclipse victim connects to. Victims connect
+    /// to attacker's nodes first.
+    #[structopt(long = "victim-outbound", default_value = "8")]
+    pub victim_outbound: usize,
+
+    /// Write a trace of how transactions reached each node to this file as JSON Lines.
+    #[structopt(long = "trace", parse(from_os_str))]
+    pub trace: Option<PathBuf>,
+
+    /// Share of transactions to trace, e.g. `0.1`.
+    #[structopt(
+        long = "trace-sample",
+        default_value = "1",
+        parse(try_from_str = "parse_share")
+    )]
+    pub trace_sample: f64,
+
+    /// Print detailed results instead of total traffic only.
+    #[structopt(short = "v", long = "verbose")]
+    pub verbose: bool,
 }
 
 fn main() {
     let parameters = SimulatorParameters::from_args();
 
-    let traffic_timeout = estimate_traffic_timeout_sec(&parameters);
-
-    let _ = actix::System::run(move || {
-        let tcounter = TrafficCounter::new(traffic_timeout).start();
-
-        let mut public_nodes = vec![];
-        for id in 0u32..parameters.num_public_nodes {
-            let peer_id = PeerId::Public(id);
-            let peer = peer::Peer::new(
-                peer_id,
-                parameters.use_reconciliation,
-                parameters.num_private_nodes as usize,
-                tcounter.clone(),
-                traffic_timeout,
-                parameters.seed,
-            );
-            public_nodes.push((peer_id, peer.start()));
-        }
+    let relay = if parameters.use_reconciliation {
+        RelayKind::Erlay
+    } else {
+        parameters.relay
+    };
 
-        let mut private_nodes = vec![];
-        for id in 0u32..parameters.num_private_nodes {
-            let peer_id = PeerId::Private(id);
-            let mut peer = peer::Peer::new(
-                peer_id,
-                parameters.use_reconciliation,
-                parameters.num_private_nodes as usize,
-                tcounter.clone(),
-                traffic_timeout,
-                parameters.seed,
-            );
-            for (id, pub_peer) in public_nodes.iter() {
-                peer.add_outbound_peer(*id, pub_peer.clone());
-            }
-
-            private_nodes.push((peer_id, peer.start()));
-        }
+    let mut builder = SimulationBuilder::new()
+        .relay(relay)
+        .num_private_nodes(parameters.num_private_nodes)
+        .num_public_nodes(parameters.num_public_nodes)
+        .num_spies(parameters.num_spies)
+        .seed(parameters.seed)
+        .time_scale(parameters.time_scale)
+        .duration(parameters.duration)
+        .tx_interval(parameters.tx_interval)
+        .reconciliation_capacity(parameters.reconciliation_capacity)
+        .reconciliation_interval(parameters.reconciliation_interval)
+        .churn(parameters.churn_session.map(|session| ChurnConfig {
+            session,
+            downtime: parameters.churn_downtime,
+        }));
 
-        // Interconnect public nodes
-        for (this_id, public_peer) in public_nodes.iter() {
-            for (other_id, other_public_peer) in public_nodes.iter() {
-                if *this_id != *other_id {
-                    other_public_peer.do_send(Connect {
-                        from_addr: public_peer.clone(),
-                        from_id: this_id.clone(),
-                    });
-                }
-            }
+    let (eclipse_share, victim_outbound) = (parameters.eclipse_share, parameters.victim_outbound);
+    let (block_interval, max_block_weight) =
+        (parameters.block_interval, parameters.max_block_weight);
+    let trace_sample = parameters.trace_sample;
+    builder = builder
+        .eclipse(parameters.eclipse_victims.map(|victims| EclipseConfig {
+            attacker_share: eclipse_share,
+            victims,
+            victim_outbound,
+        }))
+        .link_model(parameters.links)
+        .request_timeout(
+            parameters
+                .request_timeout
+                .unwrap_or_else(|| Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SEC)),
+        )
+        .max_retries(parameters.max_retries.unwrap_or(DEFAULT_MAX_RETRIES))
+        .salted_short_ids(!parameters.unsalted_short_ids)
+        .short_id_bits(parameters.short_id_bits)
+        .sketch(parameters.sketch.unwrap_or_default())
+        .mempool(MempoolConfig {
+            max_size: parameters.mempool_size,
+            expiry: parameters.mempool_expiry,
+        })
+        .mining(parameters.miners.map(|miners| MiningConfig {
+            miners,
+            block_interval,
+            max_block_weight,
+        }))
+        .compact_blocks(parameters.compact_blocks)
+        .fee_rate(parameters.fee_rate)
+        .min_fee_rate(parameters.min_fee_rate)
+        .child_share(parameters.child_share)
+        .max_orphans(parameters.max_orphans)
+        .replace_share(parameters.replace_share)
+        .double_spend_share(parameters.double_spend_share)
+        .trace(parameters.trace.as_ref().map(|_| trace_sample));
+
+    for (id, role) in parameters.roles {
+        builder = builder.role(id, role);
+    }
+
+    for (role, fraction) in parameters.role_fractions {
+        builder = builder.role_fraction(role, fraction);
+    }
+
+    for (a, b, model) in parameters.link_overrides {
+        builder = builder.link(a, b, model);
+    }
+
+    for fault in parameters
+        .cut_links
+        .into_iter()
+        .chain(parameters.partitions)
+    {
+        builder = builder.fault(fault);
+    }
+
+    let results = match builder.run() {
+        Ok(results) => results,
+        Err(err) => {
+            eprintln!("Simulation failed: {}", err);
+            std::process::exit(1);
         }
+    };
 
-        // Connect all private nodes to the all public nodes
-        for (this_id, private_peer) in private_nodes.iter() {
-            for (_other_id, other_public_peer) in public_nodes.iter() {
-                other_public_peer.do_send(Connect {
-                    from_addr: private_peer.clone(),
-                    from_id: this_id.clone(),
-                });
-            }
+    if let Some(path) = &parameters.trace {
+        let written =
+            File::create(path).and_then(|file| write_jsonl(&results.trace, BufWriter::new(file)));
+        if let Err(err) = written {
+            eprintln!("Can't write trace to {}: {}", path.display(), err);
+            std::process::exit(1);
         }
-    });
+    }
+
+    if parameters.verbose {
+        println!("{}", results);
+    } else {
+        println!("{}", results.total_traffic());
+    }
 }
@@ -0,0 +1,415 @@
+//! Mempool of a peer: unconfirmed transactions, limited in total size and age.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::messages::Tx;
+use crate::tx_registry::TxId;
+
+/// Fee rate of transactions in satoshis per 1000 bytes, unless set otherwise.
+pub const DEFAULT_FEE_RATE: u32 = 1000;
+
+/// Fee rate a replacement has to pay on top of the fees of the transaction it replaces,
+/// in satoshis per 1000 bytes.
+pub const INCREMENTAL_RELAY_FEE_RATE: u32 = 1000;
+
+/// Limits of the mempools of all peers.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct MempoolConfig {
+    /// Total size of transactions a mempool holds at most, in bytes. Unlimited if not set.
+    pub max_size: Option<u64>,
+
+    /// Time transactions stay in a mempool at most. Forever if not set.
+    pub expiry: Option<Duration>,
+}
+
+/// Reasons a transaction isn't added to a mempool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    /// Transaction is already in the mempool.
+    Duplicate,
+
+    /// Mempool is full of transactions that pay at least as much.
+    FeeTooLow,
+
+    /// Transaction pays less than the minimum fee rate of the mempool.
+    BelowMinFeeRate,
+
+    /// Transaction conflicts with one in the mempool and doesn't pay enough to replace it.
+    ReplacementFeeTooLow,
+}
+
+impl Display for MempoolError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            MempoolError::Duplicate => write!(f, "transaction is already in the mempool"),
+            MempoolError::FeeTooLow => write!(f, "mempool is full of better paying transactions"),
+            MempoolError::BelowMinFeeRate => write!(f, "fee rate is below the minimum"),
+            MempoolError::ReplacementFeeTooLow => {
+                write!(
+                    f,
+                    "replacement doesn't pay enough for the conflicting transaction"
+                )
+            }
+        }
+    }
+}
+
+/// Transaction in a mempool, along with the output it spends and the time it was added.
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    tx: Tx,
+    outpoint: TxId,
+    added: Duration,
+}
+
+/// Transactions a new transaction pushed out of a mempool.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inserted {
+    /// Conflicting transaction the new one replaced.
+    pub replaced: Option<Tx>,
+
+    /// Worse paying transactions evicted to make room.
+    pub evicted: Vec<Tx>,
+}
+
+/// Absolute fee of transaction `tx` in satoshis.
+fn fee(tx: &Tx) -> u64 {
+    u64::from(tx.fee_rate) * u64::from(tx.size) / 1000
+}
+
+/// Whether `tx` pays enough to replace `conflict`: a higher fee rate, and a fee that
+/// covers the fee of `conflict` plus its own relay at `INCREMENTAL_RELAY_FEE_RATE`.
+fn pays_for_replacement(tx: &Tx, conflict: &Tx) -> bool {
+    let relay_fee = u64::from(INCREMENTAL_RELAY_FEE_RATE) * u64::from(tx.size) / 1000;
+    tx.fee_rate > conflict.fee_rate && fee(tx) >= fee(conflict) + relay_fee
+}
+
+/// Eviction order: transactions with the lowest fee rate, and the newest of them, go first.
+type EvictionKey = (u32, Reverse<TxId>);
+
+fn eviction_key(tx: &Tx) -> EvictionKey {
+    (tx.fee_rate, Reverse(tx.id))
+}
+
+/// Transactions a peer keeps until they're confirmed, evicted or expire.
+#[derive(Debug, Default)]
+pub struct Mempool {
+    config: MempoolConfig,
+    min_fee_rate: u32,
+    entries: HashMap<TxId, Entry>,
+    by_fee_rate: BTreeSet<EvictionKey>,
+
+    /// Transaction in the mempool that spends each output.
+    spenders: HashMap<TxId, TxId>,
+    size: u64,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Mempool {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Minimum fee rate of transactions, in satoshis per 1000 bytes.
+    pub fn min_fee_rate(&self) -> u32 {
+        self.min_fee_rate
+    }
+
+    /// Makes the mempool reject transactions that pay less than `fee_rate`.
+    pub fn set_min_fee_rate(&mut self, fee_rate: u32) {
+        self.min_fee_rate = fee_rate;
+    }
+
+    /// Adds transaction `tx` received at time `now` that spends an output of its own,
+    /// evicting the worst paying transactions if the mempool is full.
+    ///
+    /// Returns evicted transactions. Nothing is evicted if `tx` doesn't make it in.
+    pub fn insert(&mut self, tx: Tx, now: Duration) -> Result<Vec<Tx>, MempoolError> {
+        self.insert_spending(tx, tx.id, now)
+            .map(|inserted| inserted.evicted)
+    }
+
+    /// Adds transaction `tx` received at time `now` that spends `outpoint`.
+    ///
+    /// A transaction in the mempool that spends the same output is replaced if `tx`
+    /// pays enough for it, as with full replace-by-fee. Otherwise the one seen first stays.
+    pub fn insert_spending(
+        &mut self,
+        tx: Tx,
+        outpoint: TxId,
+        now: Duration,
+    ) -> Result<Inserted, MempoolError> {
+        if self.entries.contains_key(&tx.id) {
+            return Err(MempoolError::Duplicate);
+        }
+        if tx.fee_rate < self.min_fee_rate {
+            return Err(MempoolError::BelowMinFeeRate);
+        }
+
+        let conflict = self.spenders.get(&outpoint).map(|id| self.entries[id]);
+        if let Some(conflict) = conflict {
+            if !pays_for_replacement(&tx, &conflict.tx) {
+                return Err(MempoolError::ReplacementFeeTooLow);
+            }
+            self.remove(&conflict.tx.id);
+        }
+
+        match self.make_room(&tx) {
+            Ok(evicted) => {
+                let evicted = evicted
+                    .into_iter()
+                    .filter_map(|id| self.remove(&id))
+                    .collect();
+                self.add(tx, outpoint, now);
+
+                Ok(Inserted {
+                    replaced: conflict.map(|conflict| conflict.tx),
+                    evicted,
+                })
+            }
+            Err(err) => {
+                // The conflicting transaction stays if the replacement doesn't make it in
+                if let Some(conflict) = conflict {
+                    self.add(conflict.tx, conflict.outpoint, conflict.added);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Worse paying transactions to evict so that `tx` fits into the mempool.
+    fn make_room(&self, tx: &Tx) -> Result<Vec<TxId>, MempoolError> {
+        let max_size = match self.config.max_size {
+            Some(max_size) => max_size,
+            None => return Ok(vec![]),
+        };
+
+        // Make sure enough worse paying transactions can be evicted first
+        let mut freed = 0;
+        let mut evicted = vec![];
+        for key in self.by_fee_rate.iter() {
+            if self.size - freed + u64::from(tx.size) <= max_size {
+                break;
+            }
+            if *key >= eviction_key(tx) {
+                return Err(MempoolError::FeeTooLow);
+            }
+
+            let Reverse(id) = key.1;
+            freed += u64::from(self.entries[&id].tx.size);
+            evicted.push(id);
+        }
+        if self.size - freed + u64::from(tx.size) > max_size {
+            return Err(MempoolError::FeeTooLow);
+        }
+
+        Ok(evicted)
+    }
+
+    fn add(&mut self, tx: Tx, outpoint: TxId, added: Duration) {
+        self.size += u64::from(tx.size);
+        self.by_fee_rate.insert(eviction_key(&tx));
+        self.spenders.insert(outpoint, tx.id);
+        self.entries.insert(
+            tx.id,
+            Entry {
+                tx,
+                outpoint,
+                added,
+            },
+        );
+    }
+
+    /// Removes transaction `id`, e.g. once it's confirmed.
+    pub fn remove(&mut self, id: &TxId) -> Option<Tx> {
+        let entry = self.entries.remove(id)?;
+        self.size -= u64::from(entry.tx.size);
+        self.by_fee_rate.remove(&eviction_key(&entry.tx));
+        self.spenders.remove(&entry.outpoint);
+
+        Some(entry.tx)
+    }
+
+    /// Transaction in the mempool that spends `outpoint`.
+    pub fn spender(&self, outpoint: TxId) -> Option<TxId> {
+        self.spenders.get(&outpoint).cloned()
+    }
+
+    /// Removes transactions that stayed in the mempool longer than allowed by time `now`.
+    pub fn expire(&mut self, now: Duration) -> Vec<Tx> {
+        let expiry = match self.config.expiry {
+            Some(expiry) => expiry,
+            None => return vec![],
+        };
+
+        let expired = self
+            .entries
+            .values()
+            .filter(|entry| entry.added + expiry <= now)
+            .map(|entry| entry.tx.id)
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|id| self.remove(&id))
+            .collect()
+    }
+
+    pub fn get(&self, id: &TxId) -> Option<&Tx> {
+        self.entries.get(id).map(|entry| &entry.tx)
+    }
+
+    pub fn contains(&self, id: &TxId) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    /// All transactions, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Tx> {
+        self.entries.values().map(|entry| &entry.tx)
+    }
+
+    /// All transactions, in the order they were added.
+    pub fn iter_by_arrival(&self) -> impl Iterator<Item = &Tx> {
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| (entry.added, entry.tx.id));
+
+        entries.into_iter().map(|entry| &entry.tx)
+    }
+
+    /// All transactions, the best paying and the oldest of them first.
+    pub fn iter_by_fee_rate(&self) -> impl Iterator<Item = &Tx> {
+        self.by_fee_rate
+            .iter()
+            .rev()
+            .map(move |(_, Reverse(id))| &self.entries[id].tx)
+    }
+
+    /// Total size of transactions in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Inserted, Mempool, MempoolConfig, MempoolError};
+    use crate::messages::Tx;
+    use crate::tx_registry::TxId;
+    use std::time::Duration;
+
+    fn tx(id: u32, fee_rate: u32) -> Tx {
+        Tx {
+            id: TxId(id),
+            size: 100,
+            fee_rate,
+        }
+    }
+
+    #[test]
+    pub fn test_mempool_eviction() {
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_size: Some(300),
+            expiry: None,
+        });
+        let now = Duration::from_secs(0);
+
+        assert_eq!(mempool.insert(tx(0, 2000), now), Ok(vec![]));
+        assert_eq!(mempool.insert(tx(1, 1000), now), Ok(vec![]));
+        assert_eq!(mempool.insert(tx(2, 1000), now), Ok(vec![]));
+        assert_eq!(
+            mempool.insert(tx(2, 1000), now),
+            Err(MempoolError::Duplicate)
+        );
+
+        // Paying as much as the worst transactions isn't enough
+        assert_eq!(
+            mempool.insert(tx(3, 1000), now),
+            Err(MempoolError::FeeTooLow)
+        );
+        assert_eq!(mempool.len(), 3);
+
+        // The newest of the worst paying transactions goes first
+        assert_eq!(mempool.insert(tx(4, 1500), now), Ok(vec![tx(2, 1000)]));
+        assert_eq!(mempool.size(), 300);
+
+        // Large transaction makes room for itself if it pays more than everything it evicts
+        let large = Tx {
+            size: 250,
+            ..tx(5, 1800)
+        };
+        assert_eq!(mempool.insert(large, now), Err(MempoolError::FeeTooLow));
+        let large = Tx {
+            size: 250,
+            ..tx(5, 3000)
+        };
+        assert_eq!(mempool.insert(large, now).map(|e| e.len()), Ok(3));
+        assert_eq!(mempool.size(), 250);
+
+        mempool.set_min_fee_rate(5000);
+        assert_eq!(
+            mempool.insert(tx(6, 4000), now),
+            Err(MempoolError::BelowMinFeeRate)
+        );
+    }
+
+    #[test]
+    pub fn test_mempool_replacement() {
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        let now = Duration::from_secs(0);
+        mempool.insert(tx(0, 1000), now).unwrap();
+
+        // Conflicting spends have to pay the incremental relay fee on top
+        assert_eq!(
+            mempool.insert_spending(tx(1, 1500), TxId(0), now),
+            Err(MempoolError::ReplacementFeeTooLow)
+        );
+        assert_eq!(
+            mempool.insert_spending(tx(1, 2000), TxId(0), now),
+            Ok(Inserted {
+                replaced: Some(tx(0, 1000)),
+                evicted: vec![],
+            })
+        );
+        assert_eq!(mempool.spender(TxId(0)), Some(TxId(1)));
+
+        // The original doesn't make it back in
+        assert_eq!(
+            mempool.insert(tx(0, 1000), now),
+            Err(MempoolError::ReplacementFeeTooLow)
+        );
+        assert_eq!(mempool.len(), 1);
+
+        mempool.remove(&TxId(1));
+        assert_eq!(mempool.spender(TxId(0)), None);
+    }
+
+    #[test]
+    pub fn test_mempool_expiry() {
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_size: None,
+            expiry: Some(Duration::from_secs(10)),
+        });
+
+        mempool.insert(tx(0, 1000), Duration::from_secs(0)).unwrap();
+        mempool.insert(tx(1, 1000), Duration::from_secs(5)).unwrap();
+        assert_eq!(mempool.expire(Duration::from_secs(9)), vec![]);
+        assert_eq!(mempool.expire(Duration::from_secs(10)), vec![tx(0, 1000)]);
+
+        assert_eq!(mempool.remove(&TxId(1)), Some(tx(1, 1000)));
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.size(), 0);
+    }
+}
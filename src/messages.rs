@@ -1,20 +1,41 @@
+use crate::block::{Block, BlockId, BLOCK_HEADER_BYTES};
+use crate::compact_block::{BLOCK_HASH_BYTES, NONCE_BYTES, SHORT_ID_BYTES};
 use crate::peer::{Peer, PeerId};
-use crate::recset::ShortId;
+use crate::trace::Mechanism;
+use crate::tx_registry::TxId;
 use actix::prelude::*;
-use siphasher::sip::SipHasher;
-use std::hash::Hasher;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-#[derive(Copy, Clone)]
-pub struct Tx(pub [u8; 1024]);
+/// Size of the header every message has on the wire: magic, command, payload length
+/// and checksum.
+pub const MESSAGE_HEADER_BYTES: u64 = 24;
 
-impl ShortId<u64> for Tx {
-    fn short_id(&self) -> u64 {
-        let mut hasher = SipHasher::new_with_keys(0xDEu64, 0xADu64);
-        hasher.write(&self.0);
-        hasher.finish()
+/// Size of a `CompactSize` count of `n` items on the wire.
+pub fn compact_size_len(n: usize) -> u64 {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
     }
 }
 
+/// Size of a list of `n` short IDs of `bits` bits each on the wire, along with its length.
+pub fn short_ids_len(n: usize, bits: u32) -> u64 {
+    compact_size_len(n) + n as u64 * u64::from(bits.div_ceil(8))
+}
+
+/// Handle to a transaction stored in the `TxRegistry`.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct Tx {
+    pub id: TxId,
+    /// Logical size of the transaction body in bytes.
+    pub size: u32,
+    /// Fee rate in satoshis per 1000 bytes.
+    pub fee_rate: u32,
+}
+
 #[derive(Copy, Clone, Message)]
 pub struct PeerTx {
     pub from: PeerId,
@@ -25,6 +46,32 @@ pub struct PeerTx {
 pub struct Connect {
     pub from_addr: Addr<Peer>,
     pub from_id: PeerId,
+    /// Salt of the sender's short IDs, see `ShortIdKey::from_salts`.
+    pub salt: u64,
+}
+
+/// Tells a peer to create a transaction that double-spends `original`. It's part of
+/// the simulated workload rather than the protocol, so it takes no traffic.
+#[derive(Copy, Clone, Message)]
+pub struct DoubleSpend {
+    pub original: Tx,
+}
+
+#[derive(Clone, Message)]
+pub struct Disconnect {
+    pub from_id: PeerId,
+}
+
+#[derive(Clone, Message)]
+pub struct Inv {
+    pub from_addr: Addr<Peer>,
+    pub from_id: PeerId,
+    pub txids: Vec<u64>,
+    /// Width of the short IDs, which sets their size on the wire.
+    pub short_id_bits: u32,
+    /// Mechanism announced transactions are requested by, only used to trace them.
+    /// It takes no traffic.
+    pub mechanism: Mechanism,
 }
 
 #[derive(Clone, Message)]
@@ -38,14 +85,88 @@ pub struct ReconcileRequest {
 pub struct ReconcileResult {
     pub from_addr: Addr<Peer>,
     pub from_id: PeerId,
+
+    /// Whether the sender could decode the difference. If it couldn't, both sides
+    /// announce their whole mempools instead.
+    pub success: bool,
+
+    /// Short IDs of transactions the sender lacks, which the receiver should send.
     pub missing: Vec<u64>,
+
+    /// Width of the short IDs, which sets their size on the wire.
+    pub short_id_bits: u32,
 }
 
+/// Request for transactions by their short IDs.
 #[derive(Clone, Message)]
-pub struct TxRequest {
+pub struct GetTxs {
     pub from_addr: Addr<Peer>,
     pub from_id: PeerId,
-    pub txid: u64,
+    pub txids: Vec<u64>,
+    /// Width of the short IDs, which sets their size on the wire.
+    pub short_id_bits: u32,
+}
+
+/// Batch of transactions, sent in response to `GetTxs` or pushed after reconciliation.
+#[derive(Clone, Message)]
+pub struct Txs {
+    pub from: PeerId,
+    pub txs: Vec<Tx>,
+}
+
+/// Minimum fee rate of transactions the sender wants to hear about, in satoshis per 1000 bytes.
+#[derive(Copy, Clone, Message)]
+pub struct FeeFilter {
+    pub from_id: PeerId,
+    pub fee_rate: u32,
+}
+
+/// Full block, relayed to every connection once it's found.
+#[derive(Clone, Message)]
+pub struct PeerBlock {
+    pub from: PeerId,
+    pub block: Arc<Block>,
+}
+
+/// Block announced by the short IDs of its transactions.
+///
+/// Carries the full block only so that the receiver can tell which transactions
+/// its short IDs really stand for; its traffic doesn't include it.
+#[derive(Clone, Message)]
+pub struct CompactBlock {
+    pub from_addr: Addr<Peer>,
+    pub from_id: PeerId,
+    pub nonce: u64,
+    pub short_ids: Vec<u64>,
+    pub block: Arc<Block>,
+}
+
+/// Request for transactions of a compact block by their indexes in the block.
+#[derive(Clone, Message)]
+pub struct GetBlockTxn {
+    pub from_addr: Addr<Peer>,
+    pub from_id: PeerId,
+    pub block_id: BlockId,
+    pub indexes: Vec<usize>,
+}
+
+/// Transactions of a block, sent in response to `GetBlockTxn`.
+#[derive(Clone, Message)]
+pub struct BlockTxn {
+    pub from: PeerId,
+    pub block_id: BlockId,
+    pub txs: Vec<Tx>,
+}
+
+/// Message that is delivered to a peer after a delay, used to model slow links.
+/// Its traffic is accounted for as the traffic of the inner message.
+pub struct Delayed<M> {
+    pub msg: M,
+    pub delay: std::time::Duration,
+}
+
+impl<M> Message for Delayed<M> {
+    type Result = ();
 }
 
 #[derive(Debug, Clone, Message)]
@@ -53,6 +174,39 @@ pub struct TrafficReport {
     pub from_id: PeerId,
     pub bytes_sent: u64,
     pub bytes_received: u64,
+    pub messages_dropped: u64,
+    pub bytes_dropped: u64,
+    pub requests_retried: u64,
+    pub peers_disconnected: u64,
+    pub short_ids_added: u64,
+    pub short_id_collisions: u64,
+    pub sketches_sent: u64,
+    pub sketch_bytes: u64,
+    pub reconciliations: u64,
+    pub decode_failures: u64,
+    pub set_differences: u64,
+    pub misbehaviour: HashMap<PeerId, u64>,
+    pub latencies: Vec<f64>,
+    pub known_origins: HashMap<PeerId, u32>,
+    pub mempool_size: u64,
+    pub txs_evicted: u64,
+    pub txs_expired: u64,
+    pub txs_rejected: u64,
+    pub blocks_mined: u64,
+    pub txs_confirmed: u64,
+    pub block_latencies: Vec<f64>,
+    pub compact_blocks: u64,
+    pub compact_blocks_reconstructed: u64,
+    pub compact_block_failures: u64,
+    pub block_round_trips: u64,
+    pub block_extra_bytes: u64,
+    pub txs_filtered: u64,
+    pub orphans: HashMap<Mechanism, u64>,
+    pub orphans_resolved: u64,
+    pub orphans_evicted: u64,
+    pub txs_replaced: u64,
+    pub replacements_rejected: u64,
+    pub conflict_versions: HashMap<TxId, (TxId, f64)>,
 }
 
 pub trait Traffic {
@@ -61,30 +215,263 @@ pub trait Traffic {
 
 impl Traffic for PeerTx {
     fn size_bytes(&self) -> u64 {
-        (std::mem::size_of::<Tx>() + std::mem::size_of::<PeerId>()) as u64
+        MESSAGE_HEADER_BYTES + u64::from(self.data.size)
     }
 }
 
 impl Traffic for Connect {
     fn size_bytes(&self) -> u64 {
-        std::mem::size_of::<PeerId>() as u64
+        // Reconciliation protocol version and salt, as in BIP 330's `sendtxrcncl`
+        MESSAGE_HEADER_BYTES + (std::mem::size_of::<u32>() + std::mem::size_of::<u64>()) as u64
+    }
+}
+
+impl Traffic for Disconnect {
+    fn size_bytes(&self) -> u64 {
+        MESSAGE_HEADER_BYTES
+    }
+}
+
+impl Traffic for Inv {
+    fn size_bytes(&self) -> u64 {
+        MESSAGE_HEADER_BYTES + short_ids_len(self.txids.len(), self.short_id_bits)
     }
 }
 
 impl Traffic for ReconcileRequest {
     fn size_bytes(&self) -> u64 {
-        (std::mem::size_of::<PeerId>() + self.sketch.len()) as u64
+        MESSAGE_HEADER_BYTES + compact_size_len(self.sketch.len()) + self.sketch.len() as u64
     }
 }
 
 impl Traffic for ReconcileResult {
     fn size_bytes(&self) -> u64 {
-        (std::mem::size_of::<PeerId>() + self.missing.len() * std::mem::size_of::<u64>()) as u64
+        // Success flag, as in BIP 330's `reconcildiff`
+        MESSAGE_HEADER_BYTES + 1 + short_ids_len(self.missing.len(), self.short_id_bits)
+    }
+}
+
+impl Traffic for GetTxs {
+    fn size_bytes(&self) -> u64 {
+        MESSAGE_HEADER_BYTES + short_ids_len(self.txids.len(), self.short_id_bits)
+    }
+}
+
+impl Traffic for Txs {
+    fn size_bytes(&self) -> u64 {
+        let bodies = self.txs.iter().map(|tx| u64::from(tx.size)).sum::<u64>();
+        MESSAGE_HEADER_BYTES + compact_size_len(self.txs.len()) + bodies
+    }
+}
+
+impl Traffic for FeeFilter {
+    fn size_bytes(&self) -> u64 {
+        // Fee rate is sent as a 64-bit integer
+        MESSAGE_HEADER_BYTES + std::mem::size_of::<u64>() as u64
+    }
+}
+
+impl Traffic for PeerBlock {
+    fn size_bytes(&self) -> u64 {
+        let txs = self.block.txs.len();
+        MESSAGE_HEADER_BYTES + BLOCK_HEADER_BYTES + compact_size_len(txs) + self.block.txs_size()
+    }
+}
+
+impl Traffic for CompactBlock {
+    fn size_bytes(&self) -> u64 {
+        let ids = self.short_ids.len() as u64 * SHORT_ID_BYTES;
+
+        // No prefilled transactions
+        MESSAGE_HEADER_BYTES
+            + BLOCK_HEADER_BYTES
+            + NONCE_BYTES
+            + compact_size_len(self.short_ids.len())
+            + ids
+            + compact_size_len(0)
+    }
+}
+
+impl Traffic for GetBlockTxn {
+    fn size_bytes(&self) -> u64 {
+        // Indexes are sent as differences from the previous one
+        let mut previous = None;
+        let mut indexes = 0;
+        for index in self.indexes.iter() {
+            let diff = previous.map_or(*index, |previous| index - previous - 1);
+            indexes += compact_size_len(diff);
+            previous = Some(*index);
+        }
+
+        MESSAGE_HEADER_BYTES + BLOCK_HASH_BYTES + compact_size_len(self.indexes.len()) + indexes
     }
 }
 
-impl Traffic for TxRequest {
+impl Traffic for BlockTxn {
     fn size_bytes(&self) -> u64 {
-        (std::mem::size_of::<PeerId>() + std::mem::size_of::<u64>()) as u64
+        let bodies = self.txs.iter().map(|tx| u64::from(tx.size)).sum::<u64>();
+        MESSAGE_HEADER_BYTES + BLOCK_HASH_BYTES + compact_size_len(self.txs.len()) + bodies
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        compact_size_len, BlockTxn, CompactBlock, Connect, Disconnect, FeeFilter, GetBlockTxn,
+        GetTxs, Inv, PeerBlock, PeerTx, ReconcileRequest, ReconcileResult, Traffic, Tx, Txs,
+        MESSAGE_HEADER_BYTES,
+    };
+    use crate::block::{Block, BlockId};
+    use crate::mempool::DEFAULT_FEE_RATE;
+    use crate::peer::PeerId;
+    use crate::trace::Mechanism;
+    use crate::tx_registry::TxId;
+    use actix::dev::channel::channel;
+    use actix::Addr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn tx(id: u32) -> Tx {
+        Tx {
+            id: TxId(id),
+            size: 1024,
+            fee_rate: DEFAULT_FEE_RATE,
+        }
+    }
+
+    #[test]
+    pub fn test_batch_sizes() {
+        assert_eq!(compact_size_len(0), 1);
+        assert_eq!(compact_size_len(0xfc), 1);
+        assert_eq!(compact_size_len(0xfd), 3);
+        assert_eq!(compact_size_len(0x1_0000), 5);
+
+        let txs = Txs {
+            from: PeerId::Public(0),
+            txs: (0..300).map(tx).collect(),
+        };
+        assert_eq!(txs.size_bytes(), MESSAGE_HEADER_BYTES + 3 + 300 * 1024);
+
+        let block_txn = BlockTxn {
+            from: PeerId::Public(0),
+            block_id: BlockId {
+                miner: PeerId::Public(1),
+                number: 0,
+            },
+            txs: (0..2).map(tx).collect(),
+        };
+        assert_eq!(
+            block_txn.size_bytes(),
+            MESSAGE_HEADER_BYTES + 32 + 1 + 2 * 1024
+        );
+    }
+
+    #[test]
+    pub fn test_message_sizes() {
+        let from_addr = Addr::new(channel(1).0);
+        let from_id = PeerId::Private(0);
+        let block_id = BlockId {
+            miner: PeerId::Public(1),
+            number: 0,
+        };
+        let block = Arc::new(Block {
+            id: block_id,
+            created: Duration::from_secs(0),
+            txs: (0..2).map(tx).collect(),
+        });
+
+        let peer_tx = PeerTx {
+            from: from_id,
+            data: tx(0),
+        };
+        assert_eq!(peer_tx.size_bytes(), 1048);
+
+        let connect = Connect {
+            from_addr: from_addr.clone(),
+            from_id,
+            salt: 0,
+        };
+        assert_eq!(connect.size_bytes(), 36);
+        assert_eq!(Disconnect { from_id }.size_bytes(), 24);
+
+        let inv = Inv {
+            from_addr: from_addr.clone(),
+            from_id,
+            txids: vec![1, 2, 3],
+            short_id_bits: 64,
+            mechanism: Mechanism::Request,
+        };
+        assert_eq!(inv.size_bytes(), 49);
+        let inv = Inv {
+            short_id_bits: 20,
+            ..inv
+        };
+        assert_eq!(inv.size_bytes(), 34);
+
+        let request = ReconcileRequest {
+            from_addr: from_addr.clone(),
+            from_id,
+            sketch: vec![0; 32],
+        };
+        assert_eq!(request.size_bytes(), 57);
+
+        let result = ReconcileResult {
+            from_addr: from_addr.clone(),
+            from_id,
+            success: true,
+            missing: vec![1, 2, 3],
+            short_id_bits: 64,
+        };
+        assert_eq!(result.size_bytes(), 50);
+
+        let get_txs = GetTxs {
+            from_addr: from_addr.clone(),
+            from_id,
+            txids: vec![1, 2, 3],
+            short_id_bits: 32,
+        };
+        assert_eq!(get_txs.size_bytes(), 37);
+
+        let txs = Txs {
+            from: from_id,
+            txs: (0..2).map(tx).collect(),
+        };
+        assert_eq!(txs.size_bytes(), 2073);
+
+        let fee_filter = FeeFilter {
+            from_id,
+            fee_rate: DEFAULT_FEE_RATE,
+        };
+        assert_eq!(fee_filter.size_bytes(), 32);
+
+        let peer_block = PeerBlock {
+            from: from_id,
+            block: block.clone(),
+        };
+        assert_eq!(peer_block.size_bytes(), 2153);
+
+        let compact_block = CompactBlock {
+            from_addr: from_addr.clone(),
+            from_id,
+            nonce: 0,
+            short_ids: vec![1, 2],
+            block,
+        };
+        assert_eq!(compact_block.size_bytes(), 126);
+
+        let get_block_txn = GetBlockTxn {
+            from_addr,
+            from_id,
+            block_id,
+            indexes: vec![0, 1, 5],
+        };
+        assert_eq!(get_block_txn.size_bytes(), 60);
+
+        let block_txn = BlockTxn {
+            from: from_id,
+            block_id,
+            txs: (0..2).map(tx).collect(),
+        };
+        assert_eq!(block_txn.size_bytes(), 2105);
     }
 }
@@ -0,0 +1,139 @@
+//! Orphan pool: transactions that arrived before the unconfirmed transaction they spend.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::messages::Tx;
+use crate::peer::PeerId;
+use crate::trace::Mechanism;
+use crate::tx_registry::TxId;
+
+/// Number of orphans a peer keeps at most, unless set otherwise.
+pub const DEFAULT_MAX_ORPHANS: usize = 100;
+
+#[derive(Debug, Copy, Clone)]
+struct Orphan {
+    tx: Tx,
+    parent: TxId,
+
+    /// Peer the orphan was received from, which should know its parent, and how.
+    from: PeerId,
+    mechanism: Mechanism,
+}
+
+/// Orphans waiting for their parents, the oldest of them evicted first once there
+/// are too many.
+#[derive(Debug, Default)]
+pub struct OrphanPool {
+    max_orphans: usize,
+    orphans: HashMap<TxId, Orphan>,
+    order: VecDeque<TxId>,
+}
+
+impl OrphanPool {
+    pub fn new(max_orphans: usize) -> Self {
+        OrphanPool {
+            max_orphans,
+            ..Default::default()
+        }
+    }
+
+    /// Adds transaction `tx` spending missing transaction `parent`, received from peer `from`
+    /// by `mechanism`.
+    ///
+    /// Returns the orphans evicted to make room for it.
+    pub fn insert(&mut self, tx: Tx, parent: TxId, from: PeerId, mechanism: Mechanism) -> Vec<Tx> {
+        if self.orphans.contains_key(&tx.id) {
+            return vec![];
+        }
+
+        let orphan = Orphan {
+            tx,
+            parent,
+            from,
+            mechanism,
+        };
+        self.orphans.insert(tx.id, orphan);
+        self.order.push_back(tx.id);
+
+        let mut evicted = vec![];
+        while self.orphans.len() > self.max_orphans {
+            let oldest = match self.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if let Some(orphan) = self.orphans.remove(&oldest) {
+                evicted.push(orphan.tx);
+            }
+        }
+
+        evicted
+    }
+
+    /// Removes and returns orphans that spend transaction `parent`, along with the peers
+    /// they were received from and how.
+    pub fn take_children(&mut self, parent: TxId) -> Vec<(Tx, PeerId, Mechanism)> {
+        let children = self
+            .orphans
+            .values()
+            .filter(|orphan| orphan.parent == parent)
+            .map(|orphan| orphan.tx.id)
+            .collect::<Vec<_>>();
+        self.order.retain(|id| !children.contains(id));
+
+        let mut children = children
+            .into_iter()
+            .filter_map(|id| self.orphans.remove(&id))
+            .map(|orphan| (orphan.tx, orphan.from, orphan.mechanism))
+            .collect::<Vec<_>>();
+        children.sort_by_key(|(tx, _, _)| tx.id);
+
+        children
+    }
+
+    pub fn contains(&self, id: &TxId) -> bool {
+        self.orphans.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OrphanPool;
+    use crate::mempool::DEFAULT_FEE_RATE;
+    use crate::messages::Tx;
+    use crate::peer::PeerId;
+    use crate::trace::Mechanism;
+    use crate::tx_registry::TxId;
+
+    #[test]
+    pub fn test_orphan_pool() {
+        let tx = |id| Tx {
+            id: TxId(id),
+            size: 100,
+            fee_rate: DEFAULT_FEE_RATE,
+        };
+        let from = PeerId::Public(0);
+        let mut orphans = OrphanPool::new(2);
+
+        let flood = Mechanism::Flood;
+        assert!(orphans.insert(tx(1), TxId(0), from, flood).is_empty());
+        assert!(orphans.insert(tx(2), TxId(0), from, flood).is_empty());
+        assert!(orphans.insert(tx(2), TxId(0), from, flood).is_empty());
+        assert_eq!(orphans.len(), 2);
+
+        // The oldest orphan goes first
+        assert_eq!(orphans.insert(tx(4), TxId(3), from, flood), vec![tx(1)]);
+        assert!(!orphans.contains(&TxId(1)));
+
+        assert_eq!(orphans.take_children(TxId(0)), vec![(tx(2), from, flood)]);
+        assert!(orphans.take_children(TxId(0)).is_empty());
+        assert_eq!(orphans.len(), 1);
+    }
+}
@@ -1,27 +1,161 @@
 use actix::prelude::*;
 
-use byteorder::{ByteOrder, LittleEndian};
 use rand::{self, seq::SliceRandom, Rng, SeedableRng};
 use rand_xorshift::XorShiftRng;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Error, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::recset::{RecSet, ShortId};
-use crate::RECONCIL_TIMEOUT_SEC;
-
+use crate::adversary::{Misbehaviour, Role};
+use crate::block::{Block, BlockId, MiningConfig};
+use crate::compact_block::{self, Reconstruction};
+use crate::recset::{truncate_short_id, RecSet, RecSetError, SetDifference};
+use crate::relay::{ProtocolMessage, RelayStrategy};
+use crate::short_ids::{ShortIdKey, ShortIds};
+use crate::sketch::SketchKind;
+
+use crate::churn::ChurnConfig;
+use crate::clock::Clock;
+use crate::distribution::ValueDistribution;
+use crate::faults::FaultSchedule;
+use crate::link::{Delivery, NetworkModel};
+use crate::mempool::{Mempool, MempoolConfig, MempoolError, INCREMENTAL_RELAY_FEE_RATE};
 use crate::messages::{
-    Connect, PeerTx, ReconcileRequest, ReconcileResult, Traffic, TrafficReport, Tx, TxRequest,
+    BlockTxn, CompactBlock, Connect, Delayed, Disconnect, DoubleSpend, FeeFilter, GetBlockTxn,
+    GetTxs, Inv, PeerBlock, PeerTx, ReconcileRequest, ReconcileResult, Traffic, TrafficReport, Tx,
+    Txs,
 };
+use crate::orphans::OrphanPool;
+use crate::spy::SpyLog;
+use crate::trace::{Mechanism, TraceEvent, TxTrace};
 use crate::traffic_counter::TrafficCounter;
+use crate::tx_registry::{TxId, TxRegistry};
+
+/// Number of transaction requests a peer serves to another one per simulated second.
+pub const MAX_TX_REQUESTS_PER_SEC: u32 = 1000;
+
+/// Number of transactions that left the mempool a peer remembers, so it doesn't request
+/// them again, like the filter of recently rejected transactions in Bitcoin Core.
+pub const MAX_REMOVED_TXS: usize = 10_000;
+
+/// Misbehaviour score at which a peer is disconnected and not accepted again,
+/// as in Bitcoin Core.
+pub const DISCOURAGEMENT_THRESHOLD: u64 = 100;
 
-#[derive(Copy, Clone, Hash, PartialEq, Eq)]
+/// Number of transactions a `Role::TxRequestSpam` peer requests from each connection per second.
+const SPAM_TX_REQUESTS_PER_SEC: u32 = 5000;
+
+/// Number of IDs a `Role::OversizedResult` peer puts in a reconciliation result.
+const OVERSIZED_RESULT_LEN: usize = 10_000;
+
+/// Bit of the `u64` representation of a `PeerId` that marks private peers.
+const PRIVATE_PEER_FLAG: u64 = 1 << 32;
+
+/// Identifies a peer in the network.
+///
+/// Converts losslessly to and from `u64`: the lower 32 bits hold the index
+/// and `PRIVATE_PEER_FLAG` tells private peers from public ones.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PeerId {
     Public(u32),
     Private(u32),
 }
 
+/// Simulation-wide parameters shared by all peers.
+#[derive(Clone)]
+pub struct PeerConfig {
+    /// Capacity of reconciliation sketches.
+    pub reconciliation_capacity: usize,
+
+    /// Receives traffic reports at the end of the simulation.
+    pub traffic_counter_addr: Addr<TrafficCounter>,
+
+    /// Simulated time at which the simulation ends, in seconds.
+    pub traffic_timeout: u64,
+
+    /// Seed for a random number generator.
+    pub seed: Option<u64>,
+
+    /// Bodies of all transactions in the simulation.
+    pub registry: Arc<TxRegistry>,
+
+    pub clock: Clock,
+
+    /// Link failures and partitions injected during the simulation.
+    pub faults: Arc<FaultSchedule>,
+
+    /// Message loss and delays on links between peers.
+    pub network: Arc<NetworkModel>,
+
+    /// Time to wait for a response before sending a request again, in simulated time.
+    pub request_timeout: Duration,
+
+    /// Number of times an unanswered request is sent again.
+    pub max_retries: u32,
+
+    /// First-seen log of spy nodes, if there are any.
+    pub spies: Option<Arc<SpyLog>>,
+
+    /// Trace of how sampled transactions reach peers, if it's kept.
+    pub trace: Option<Arc<TxTrace>>,
+
+    /// Whether short IDs are salted per connection. All connections use
+    /// `ShortIdKey::UNSALTED` otherwise.
+    pub salted_short_ids: bool,
+
+    /// Width of short IDs in bits.
+    pub short_id_bits: u32,
+
+    /// Kind of reconciliation sketches.
+    pub sketch: SketchKind,
+
+    /// Makes peers go offline and come back, if set.
+    pub churn: Option<ChurnConfig>,
+
+    /// Interval between reconciliation rounds of set reconciling strategies.
+    /// Peers reconcile only once if it's not set.
+    pub reconciliation_interval: Option<Duration>,
+
+    /// Interval between transactions created by each private peer, in simulated seconds.
+    /// Private peers create a single transaction at start if it's not set.
+    pub tx_interval: Option<ValueDistribution>,
+
+    /// Size limit and expiry of mempools.
+    pub mempool: MempoolConfig,
+
+    /// Peers that mine blocks and how often, if any do.
+    pub mining: Option<MiningConfig>,
+
+    /// Whether blocks are relayed as BIP152 compact blocks instead of in full.
+    pub compact_blocks: bool,
+
+    /// Fee rates of transactions private peers create, in satoshis per 1000 bytes.
+    /// Transactions pay `DEFAULT_FEE_RATE` if it's not set.
+    pub fee_rate: Option<ValueDistribution>,
+
+    /// Minimum fee rate each peer draws for its mempool and announces to others
+    /// with `FeeFilter`. Peers accept transactions of any fee rate if it's not set.
+    pub min_fee_rate: Option<ValueDistribution>,
+
+    /// Probability that a private peer's new transaction spends its previous one,
+    /// if that one is still unconfirmed. Transactions are independent if it's not set.
+    pub child_share: Option<f64>,
+
+    /// Number of orphans each peer keeps at most.
+    pub max_orphans: usize,
+
+    /// Probability that a private peer replaces its previous transaction, if that one
+    /// is still unconfirmed, with one paying more instead of creating an independent one.
+    pub replace_share: Option<f64>,
+
+    /// Probability that a random peer the creator knows double-spends a new transaction
+    /// at the same time, paying the same fee rate.
+    pub double_spend_share: Option<f64>,
+}
+
 /// Describes single independent peer in the network.
 pub struct Peer {
     /// ID of this peer.
@@ -33,23 +167,140 @@ pub struct Peer {
     /// Inbound connections
     pub inbound: HashMap<PeerId, Addr<Peer>>,
 
-    /// Holds a mempool, set of transactions by txid
-    pub mempool: HashMap<u64, Tx>,
+    /// Every peer this peer was ever connected to, used to reconnect after going offline.
+    pub known_peers: HashMap<PeerId, Addr<Peer>>,
+
+    /// Unconfirmed transactions this peer knows.
+    pub mempool: Mempool,
 
     /// Holds set of received transactions ID from an individual peer.
-    pub received_txs: HashMap<PeerId, Vec<u64>>,
+    pub received_txs: HashMap<PeerId, Vec<TxId>>,
+
+    /// Salt of this peer's short IDs, sent to other peers when connecting.
+    pub salt: u64,
+
+    /// Salts of other peers' short IDs.
+    salts: HashMap<PeerId, u64>,
+
+    /// Transactions that left the mempool or never made it in, which aren't requested again,
+    /// the oldest of them forgotten first once there are too many.
+    removed: HashSet<TxId>,
+    removed_order: VecDeque<TxId>,
+
+    /// Transactions put under the short IDs of a connection for sketches and announcements,
+    /// and ones of them left out since their short ID collided.
+    short_ids_added: u64,
+    short_id_collisions: u64,
+
+    /// Reconciliation sketches this peer sent, and their size in bytes.
+    sketches_sent: u64,
+    sketch_bytes: u64,
+
+    /// Sketches this peer tried to decode, ones of them that couldn't be decoded,
+    /// and the total number of differences in the decoded ones.
+    reconciliations: u64,
+    decode_failures: u64,
+    set_differences: u64,
+
+    /// Transactions evicted from the mempool, expired, and not let in since it was full
+    /// or they paid less than its minimum fee rate.
+    txs_evicted: u64,
+    txs_expired: u64,
+    txs_rejected: u64,
+
+    /// Unsalted short IDs a `Role::CollidingTx` peer created colliding transactions for.
+    collided: HashSet<u64>,
+
+    /// Minimum fee rates other peers announced with `FeeFilter`.
+    fee_filters: HashMap<PeerId, u32>,
+
+    /// Transactions not announced to connections since they were below their fee filters.
+    txs_filtered: u64,
+
+    /// Transactions received before the transactions they spend.
+    orphans: OrphanPool,
+
+    /// Orphans received by the mechanism that delivered them, ones of them whose parents
+    /// arrived later, and ones evicted from the orphan pool before that.
+    orphans_received: HashMap<Mechanism, u64>,
+    orphans_resolved: u64,
+    orphans_evicted: u64,
 
-    /// Set of transactions for reconciliation with any peer
-    pub reconciliation_set: RecSet<u64>,
+    /// Transaction this peer created last, which the next one may spend or replace.
+    last_created: Option<TxId>,
+
+    /// Transactions replaced by conflicting ones that paid more, along with their
+    /// descendants, and conflicting transactions that didn't pay enough to replace.
+    txs_replaced: u64,
+    replacements_rejected: u64,
+
+    /// Transaction this peer last kept for each output, and when it changed.
+    versions: HashMap<TxId, (TxId, Duration)>,
+
+    /// Outputs spent by confirmed transactions.
+    spent: HashSet<TxId>,
+
+    /// Blocks this peer received or mined, and transactions they confirmed.
+    blocks: HashMap<BlockId, Arc<Block>>,
+    confirmed: HashSet<TxId>,
+    blocks_mined: u32,
+
+    /// Compact blocks waiting for the transactions this peer requested.
+    pending_blocks: HashMap<BlockId, Arc<Block>>,
+
+    /// Compact blocks this peer received, ones of them it reconstructed from its mempool
+    /// alone, and ones whose short IDs matched wrong transactions.
+    compact_blocks: u64,
+    compact_blocks_reconstructed: u64,
+    compact_block_failures: u64,
+
+    /// Requests for missing transactions of compact blocks, and bytes they took both ways.
+    block_round_trips: u64,
+    block_extra_bytes: u64,
+
+    /// Simulated time it took each block to reach this peer since it was found, in seconds.
+    block_latencies: Vec<f64>,
+
+    /// Whether peer is online. Offline peers have no connections and ignore all messages.
+    pub online: bool,
+
+    /// How this peer behaves towards others.
+    pub role: Role,
+
+    /// Scores of misbehaviour detected in other peers.
+    pub misbehaviour: HashMap<PeerId, u64>,
+
+    /// Transaction requests served to each peer in the current simulated second.
+    tx_requests: HashMap<PeerId, (u64, u32)>,
+
+    /// Simulated time it took each transaction to reach this peer since its creation, in seconds.
+    latencies: Vec<f64>,
 
     seed: u64,
 
     bytes_sent: u64,
     bytes_received: u64,
-    traffic_counter_addr: Addr<TrafficCounter>,
-    traffic_timeout: u64,
 
-    use_reconciliation: bool,
+    /// Messages this peer sent that never arrived.
+    messages_dropped: u64,
+    bytes_dropped: u64,
+
+    requests_retried: u64,
+
+    /// Connections dropped since their peer misbehaved.
+    peers_disconnected: u64,
+
+    /// Why transactions were requested from each peer, by their short IDs, kept for the trace.
+    requested_via: HashMap<(PeerId, u64), Mechanism>,
+
+    /// Reconciliations waiting for a result, by peer, with the ID of the latest request.
+    pending_reconciliations: HashMap<PeerId, u64>,
+    last_request_id: u64,
+
+    config: PeerConfig,
+
+    /// Relay strategy, taken out of the peer while one of its hooks runs.
+    strategy: Option<Box<dyn RelayStrategy>>,
 }
 
 impl Debug for PeerId {
@@ -61,50 +312,112 @@ impl Debug for PeerId {
     }
 }
 
+impl FromStr for PeerId {
+    type Err = String;
+
+    /// Parses peer IDs in their `Debug` form, e.g. `pub0` or `priv12`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |id: &str| {
+            id.parse::<u32>()
+                .map_err(|_| format!("invalid peer ID: {}", s))
+        };
+
+        if let Some(id) = s.strip_prefix("pub") {
+            Ok(PeerId::Public(parse(id)?))
+        } else if let Some(id) = s.strip_prefix("priv") {
+            Ok(PeerId::Private(parse(id)?))
+        } else {
+            Err(format!("invalid peer ID: {}", s))
+        }
+    }
+}
+
 impl From<u64> for PeerId {
     fn from(v: u64) -> Self {
-        if v < 1 << 16 {
-            PeerId::Public(v as u32 - 1)
+        let id = (v & u64::from(u32::MAX)) as u32;
+
+        if v & PRIVATE_PEER_FLAG == 0 {
+            PeerId::Public(id)
         } else {
-            PeerId::Private(v as u32)
+            PeerId::Private(id)
         }
     }
 }
 
-impl Into<u64> for PeerId {
-    fn into(self) -> u64 {
-        let id = match self {
-            PeerId::Public(id) => id + 1,
-            PeerId::Private(id) => (id + 1) << 16,
-        };
-
-        id as u64
+impl From<PeerId> for u64 {
+    fn from(peer_id: PeerId) -> u64 {
+        match peer_id {
+            PeerId::Public(id) => u64::from(id),
+            PeerId::Private(id) => PRIVATE_PEER_FLAG | u64::from(id),
+        }
     }
 }
 
 impl Peer {
-    pub fn new(
-        id: PeerId,
-        use_reconciliation: bool,
-        reconciliation_capacity: usize,
-        traffic_counter_addr: Addr<TrafficCounter>,
-        traffic_timeout: u64,
-        seed: Option<u64>,
-    ) -> Self {
+    pub fn new(id: PeerId, strategy: Box<dyn RelayStrategy>, config: PeerConfig) -> Self {
+        let seed = config.seed.unwrap_or(0u64).wrapping_add(id.into());
+
         Peer {
             id,
             outbound: HashMap::new(),
             inbound: HashMap::new(),
+            known_peers: HashMap::new(),
 
-            mempool: Default::default(),
+            mempool: Mempool::new(config.mempool),
             received_txs: Default::default(),
-            reconciliation_set: RecSet::new(reconciliation_capacity),
-            seed: seed.unwrap_or(0u64) + Into::<u64>::into(id),
+            salt: XorShiftRng::seed_from_u64(!seed).gen(),
+            salts: HashMap::new(),
+            removed: HashSet::new(),
+            removed_order: VecDeque::new(),
+            short_ids_added: 0,
+            short_id_collisions: 0,
+            sketches_sent: 0,
+            sketch_bytes: 0,
+            reconciliations: 0,
+            decode_failures: 0,
+            set_differences: 0,
+            txs_evicted: 0,
+            txs_expired: 0,
+            txs_rejected: 0,
+            collided: HashSet::new(),
+            fee_filters: HashMap::new(),
+            txs_filtered: 0,
+            orphans: OrphanPool::new(config.max_orphans),
+            orphans_received: HashMap::new(),
+            orphans_resolved: 0,
+            orphans_evicted: 0,
+            last_created: None,
+            txs_replaced: 0,
+            replacements_rejected: 0,
+            versions: HashMap::new(),
+            spent: HashSet::new(),
+            blocks: HashMap::new(),
+            confirmed: HashSet::new(),
+            blocks_mined: 0,
+            pending_blocks: HashMap::new(),
+            compact_blocks: 0,
+            compact_blocks_reconstructed: 0,
+            compact_block_failures: 0,
+            block_round_trips: 0,
+            block_extra_bytes: 0,
+            block_latencies: vec![],
+            online: true,
+            role: Role::Honest,
+            misbehaviour: HashMap::new(),
+            tx_requests: HashMap::new(),
+            latencies: Vec::new(),
+            seed,
             bytes_sent: 0,
             bytes_received: 0,
-            traffic_counter_addr,
-            traffic_timeout,
-            use_reconciliation,
+            messages_dropped: 0,
+            bytes_dropped: 0,
+            requests_retried: 0,
+            peers_disconnected: 0,
+            requested_via: HashMap::new(),
+            pending_reconciliations: HashMap::new(),
+            last_request_id: 0,
+            config,
+            strategy: Some(strategy),
         }
     }
 
@@ -112,270 +425,1915 @@ impl Peer {
         self.outbound.contains_key(&id)
     }
 
-    pub fn add_outbound_peer(&mut self, id: PeerId, addr: Addr<Peer>) {
+    /// Adds an outbound connection to peer `id` with short ID `salt`.
+    pub fn add_outbound_peer(&mut self, id: PeerId, addr: Addr<Peer>, salt: u64) {
+        self.known_peers.insert(id, addr.clone());
+        self.salts.insert(id, salt);
         self.outbound.insert(id, addr);
     }
 
-    fn is_public(&self) -> bool {
-        !self.inbound.is_empty()
+    pub fn is_public(&self) -> bool {
+        matches!(self.id, PeerId::Public(_))
     }
-}
-
-/// Make actor from `Peer`
-impl Actor for Peer {
-    type Context = actix::Context<Self>;
-
-    fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_later(Duration::from_secs(0), |act, _| {
-            if !act.is_public() {
-                let mut tx_data = [0u8; 1024];
-                let mut seed = [0u8; 16];
-                LittleEndian::write_u64(&mut seed, act.seed);
-                let mut rng = XorShiftRng::from_seed(seed);
-                rng.fill(&mut tx_data);
-                let tx = Tx(tx_data);
-
-                // Announce a transaction to a single random outbound peer
-                if let Some(addr) = act.outbound.values().collect::<Vec<_>>().first() {
-                    let peer_tx = PeerTx {
-                        from: act.id,
-                        data: tx,
-                    };
-
-                    addr.do_send(peer_tx);
-                    act.bytes_sent += peer_tx.size_bytes();
-                }
 
-                act.seed = rng.gen();
-            }
-        });
+    pub fn config(&self) -> &PeerConfig {
+        &self.config
+    }
 
-        ctx.run_later(Duration::from_secs(self.traffic_timeout.saturating_sub(1)), |peer, _ct| {
-            /*println!(
-                "Peer {:?} outbound connections: {:?}",
-                peer.id,
-                peer.outbound.keys().collect::<Vec<_>>()
-            );
-            println!(
-                "Peer {:?} inbound connections: {:?}",
-                peer.id,
-                peer.inbound.keys().collect::<Vec<_>>()
-            );*/
-
-            let mut txs = peer
-                .mempool
-                .values()
-                .map(|tx| tx.short_id())
-                .collect::<Vec<_>>();
-            txs.sort();
-            //println!("Peer {:?} {} txs", peer.id, txs.len());
-            let traffic_msg = TrafficReport {
-                from_id: peer.id,
-                bytes_sent: peer.bytes_sent,
-                bytes_received: peer.bytes_received,
-            };
+    /// Bodies of all transactions in the simulation.
+    pub fn registry(&self) -> &TxRegistry {
+        &self.config.registry
+    }
 
-            peer.traffic_counter_addr.do_send(traffic_msg);
-        });
+    /// Simulated time since the start of the simulation.
+    pub fn now(&self) -> Duration {
+        self.config.clock.now()
+    }
 
-        if self.use_reconciliation {
-            ctx.run_later(Duration::from_secs(RECONCIL_TIMEOUT_SEC), |peer, ctx| {
-                // Shuffle outbound peers and send reconciliation requests
-                let mut seed = [0u8; 16];
-                LittleEndian::write_u64(&mut seed, peer.seed);
-                let mut rng = XorShiftRng::from_seed(seed);
-                let peers = peer.outbound.clone();
-                {
-                    let mut peers = peers.iter().collect::<Vec<_>>();
-                    peers.shuffle(&mut rng);
-
-                    for (_, peer_addr) in peers {
-                        let sketch = peer.reconciliation_set.sketch();
-                        let msg = ReconcileRequest {
-                            from_addr: ctx.address(),
-                            from_id: peer.id,
-                            sketch,
-                        };
-
-                        peer.bytes_sent += msg.size_bytes();
-                        peer_addr.do_send(msg);
-                    }
-                }
+    /// Address of a connected peer.
+    pub fn connection(&self, id: PeerId) -> Option<Addr<Peer>> {
+        self.outbound
+            .get(&id)
+            .or_else(|| self.inbound.get(&id))
+            .cloned()
+    }
 
-                peer.seed = rng.gen();
-            });
+    /// Key of short IDs of the connection with peer `id`.
+    ///
+    /// Returns `None` until the salt of the peer arrives with its `Connect`: both sides
+    /// must use the same key, so short IDs of the connection can't be derived before that.
+    pub fn short_id_key(&self, id: PeerId) -> Option<ShortIdKey> {
+        if !self.config.salted_short_ids {
+            return Some(ShortIdKey::UNSALTED);
         }
+
+        let salt = self.salts.get(&id)?;
+        Some(ShortIdKey::from_salts(self.salt, *salt))
     }
 
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
-        Running::Stop
+    /// Short ID of transaction `tx` on the connection with peer `id`.
+    pub fn short_id(&self, id: PeerId, tx: TxId) -> Option<u64> {
+        let short_id = self.registry().short_id(tx, self.short_id_key(id)?);
+        Some(truncate_short_id(short_id, self.config.short_id_bits))
     }
-}
 
-impl Handler<PeerTx> for Peer {
-    type Result = ();
+    /// Transactions in the mempool that pass the fee filter of peer `id`, under the short IDs
+    /// of their connection. Transactions that arrived first win short ID collisions.
+    pub fn short_ids(&self, id: PeerId) -> Option<ShortIds> {
+        let key = self.short_id_key(id)?;
+        let mut short_ids = ShortIds::new(
+            key,
+            self.config.sketch,
+            self.config.reconciliation_capacity,
+            self.config.short_id_bits,
+        );
+
+        for tx in self.mempool.iter_by_arrival() {
+            if self.passes_fee_filter(id, tx) {
+                short_ids.insert(self.registry().short_id(tx.id, key), tx.id);
+            }
+        }
 
-    fn handle(&mut self, msg: PeerTx, _ctx: &mut Context<Self>) {
-        self.bytes_received += msg.size_bytes();
+        Some(short_ids)
+    }
 
-        let txid = msg.data.short_id();
+    /// Counts transactions put under short IDs for a sketch or an announcement.
+    fn count_short_ids(&mut self, short_ids: &ShortIds) {
+        self.short_ids_added += short_ids.len() as u64 + short_ids.collisions();
+        self.short_id_collisions += short_ids.collisions();
+    }
 
-        // Don't relay nor save already processed transaction
-        if self.mempool.contains_key(&txid) {
+    /// Short IDs on the connection with peer `id` of transactions this peer has or removed,
+    /// which there's no need to request.
+    fn known_short_ids(&self, id: PeerId) -> Option<HashSet<u64>> {
+        let key = self.short_id_key(id)?;
+        let bits = self.config.short_id_bits;
+
+        let known = self
+            .mempool
+            .iter()
+            .map(|tx| tx.id)
+            .chain(self.removed.iter().cloned())
+            .map(|tx| truncate_short_id(self.registry().short_id(tx, key), bits))
+            .collect();
+        Some(known)
+    }
+
+    /// Sends a message to peer `to` and accounts for its traffic.
+    /// Messages over a link cut by a fault are dropped, lossy and slow links
+    /// lose and delay them according to the `NetworkModel`.
+    pub fn send<M>(&mut self, to: PeerId, addr: &Addr<Peer>, msg: M)
+    where
+        M: Message<Result = ()> + Traffic + Send + 'static,
+        Peer: Handler<M> + Handler<Delayed<M>>,
+    {
+        let size = msg.size_bytes();
+        self.bytes_sent += size;
+
+        if self.config.faults.is_cut(self.id, to, self.now()) {
+            self.messages_dropped += 1;
+            self.bytes_dropped += size;
             return;
         }
 
-        if !self.reconciliation_set.contains(&txid) {
-            self.reconciliation_set.insert(txid);
+        let link = *self.config.network.link(self.id, to);
+        if link.is_reliable() {
+            addr.do_send(msg);
+            return;
         }
 
-        self.mempool.insert(txid, msg.data);
+        match self.with_rng(|rng| link.sample(rng)) {
+            Delivery::Lost => {
+                self.messages_dropped += 1;
+                self.bytes_dropped += size;
+            }
+            Delivery::Delayed(delay) => addr.do_send(Delayed {
+                msg,
+                delay: self.config.clock.real(delay),
+            }),
+        }
+    }
 
-        if !self.received_txs.contains_key(&msg.from) {
-            self.received_txs.insert(msg.from, vec![]);
+    /// Requests transactions from peer `to` in a single batch, and the ones
+    /// that don't arrive in time again. `mechanism` tells why they're requested.
+    pub fn request_txs(
+        &mut self,
+        ctx: &mut Context<Self>,
+        to: PeerId,
+        addr: &Addr<Peer>,
+        txids: Vec<u64>,
+        mechanism: Mechanism,
+    ) {
+        if !txids.is_empty() {
+            self.note_requests(to, &txids, Some(mechanism));
+            self.send_tx_requests(ctx, to, addr, txids, 0);
         }
+    }
 
-        if let Some(txs) = self.received_txs.get_mut(&msg.from) {
-            txs.push(txid);
+    /// Remembers why transactions with `txids` are requested from peer `to`,
+    /// or forgets it if `mechanism` is `None`.
+    fn note_requests(&mut self, to: PeerId, txids: &[u64], mechanism: Option<Mechanism>) {
+        for txid in txids {
+            match mechanism {
+                Some(mechanism) => self.requested_via.insert((to, *txid), mechanism),
+                None => self.requested_via.remove(&(to, *txid)),
+            };
         }
+    }
 
-        if self.use_reconciliation {
-            // Perform low-fanout flooding if it's a public node
-            if self.is_public() {
-                let mut seed = [0u8; 16];
-                LittleEndian::write_u64(&mut seed, self.seed);
+    /// Records that transaction `tx` reached this peer from peer `from` by `mechanism`,
+    /// unless it's already known.
+    fn trace_tx(&self, from: PeerId, tx: Tx, mechanism: Mechanism) {
+        let trace = match &self.config.trace {
+            Some(trace) => trace,
+            None => return,
+        };
 
-                let mut rng = XorShiftRng::from_seed(seed);
+        let known = self.mempool.contains(&tx.id)
+            || self.confirmed.contains(&tx.id)
+            || self.orphans.contains(&tx.id);
+        if !known {
+            trace.record(TraceEvent {
+                tx: tx.id,
+                peer: self.id,
+                from,
+                time: self.now(),
+                mechanism,
+            });
+        }
+    }
 
-                let peers = self.outbound.clone();
-                {
-                    let mut peers = peers.iter().collect::<Vec<_>>();
-                    peers.shuffle(&mut rng);
-
-                    for (_, peer) in peers.into_iter().take(8) {
-                        let new_msg = PeerTx {
-                            from: self.id,
-                            data: msg.data,
-                        };
-
-                        peer.do_send(new_msg);
-                        self.bytes_sent += new_msg.size_bytes();
-                    }
-                }
+    fn send_tx_requests(
+        &mut self,
+        ctx: &mut Context<Self>,
+        to: PeerId,
+        addr: &Addr<Peer>,
+        txids: Vec<u64>,
+        attempt: u32,
+    ) {
+        let get_txs = GetTxs {
+            from_addr: ctx.address(),
+            from_id: self.id,
+            txids: txids.clone(),
+            short_id_bits: self.config.short_id_bits,
+        };
+        self.send(to, addr, get_txs);
 
-                self.seed = rng.gen();
+        let timeout = self.config.clock.real(self.config.request_timeout);
+        ctx.run_later(timeout, move |peer, ctx| {
+            if !peer.online {
+                return;
             }
-        } else {
-            // Just flood the transaction to outbound and inbound peers
-            for (id, peer) in self.outbound.iter().chain(self.inbound.iter()) {
-                if *id == msg.from {
-                    continue;
-                }
 
-                let new_msg = PeerTx {
-                    from: self.id,
-                    data: msg.data,
-                };
+            let known = peer.known_short_ids(to).unwrap_or_default();
+            let missing = txids
+                .into_iter()
+                .filter(|txid| !known.contains(txid))
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                return;
+            }
+            if attempt >= peer.config.max_retries {
+                peer.note_requests(to, &missing, None);
+                return;
+            }
 
-                peer.do_send(new_msg);
-                self.bytes_sent += new_msg.size_bytes();
+            // Retried requests keep the mechanism they were first made by
+            if let Some(addr) = peer.connection(to) {
+                peer.requests_retried += 1;
+                peer.send_tx_requests(ctx, to, &addr, missing, attempt + 1);
             }
+        });
+    }
+
+    /// Sends transactions with `short_ids` to peer `to` in a single batch, the ones
+    /// this peer has and its role lets it serve.
+    pub fn push_txs<T: IntoIterator<Item = u64>>(
+        &mut self,
+        to: PeerId,
+        addr: &Addr<Peer>,
+        short_ids: T,
+    ) {
+        let ids = match self.short_ids(to) {
+            Some(ids) => ids,
+            None => return,
+        };
+        let txs = short_ids
+            .into_iter()
+            .filter_map(|short_id| ids.get(short_id))
+            .filter_map(|id| self.mempool.get(&id))
+            .filter(|tx| self.serves(to, tx))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if !txs.is_empty() {
+            let txs_msg = Txs { from: self.id, txs };
+            self.send(to, addr, txs_msg);
         }
     }
-}
 
-impl Handler<Connect> for Peer {
-    type Result = ();
+    /// Announces all transactions in the mempool this peer would relay to peer `to`,
+    /// which requests the ones it lacks by `mechanism`.
+    pub fn announce_mempool(
+        &mut self,
+        ctx: &mut Context<Self>,
+        to: PeerId,
+        addr: &Addr<Peer>,
+        mechanism: Mechanism,
+    ) {
+        let short_ids = match self.short_ids(to) {
+            Some(short_ids) => short_ids,
+            None => return,
+        };
+        self.count_short_ids(&short_ids);
+
+        let inv = Inv {
+            from_addr: ctx.address(),
+            from_id: self.id,
+            txids: short_ids
+                .iter()
+                .filter(|(_, tx)| {
+                    let tx = self.mempool.get(tx);
+                    tx.is_some_and(|tx| self.relays_to(to, tx))
+                })
+                .map(|(short_id, _)| short_id)
+                .collect(),
+            short_id_bits: self.config.short_id_bits,
+            mechanism,
+        };
 
-    fn handle(&mut self, msg: Connect, ctx: &mut Context<Self>) {
-        self.bytes_received += msg.size_bytes();
+        self.send(to, addr, inv);
+    }
+
+    /// Sends a sketch of the reconciliation set to peer `to`, and again if no
+    /// `ReconcileResult` arrives in time. A result saying the sketch couldn't be
+    /// decoded is an answer too, so it isn't retried.
+    pub fn request_reconciliation(
+        &mut self,
+        ctx: &mut Context<Self>,
+        to: PeerId,
+        addr: &Addr<Peer>,
+    ) {
+        self.send_reconcile_request(ctx, to, addr, 0);
+    }
+
+    fn send_reconcile_request(
+        &mut self,
+        ctx: &mut Context<Self>,
+        to: PeerId,
+        addr: &Addr<Peer>,
+        attempt: u32,
+    ) {
+        let sketch = match self.reconciliation_sketch(to) {
+            Some(sketch) => sketch,
+            None => return,
+        };
+
+        self.last_request_id += 1;
+        let request_id = self.last_request_id;
+        self.pending_reconciliations.insert(to, request_id);
+
+        let msg = ReconcileRequest {
+            from_addr: ctx.address(),
+            from_id: self.id,
+            sketch,
+        };
+        self.sketches_sent += 1;
+        self.sketch_bytes += msg.sketch.len() as u64;
+        self.send(to, addr, msg);
+
+        let timeout = self.config.clock.real(self.config.request_timeout);
+        ctx.run_later(timeout, move |peer, ctx| {
+            // Answered, or superseded by a newer request
+            if peer.pending_reconciliations.get(&to) != Some(&request_id) {
+                return;
+            }
+
+            peer.pending_reconciliations.remove(&to);
+            if !peer.online || attempt >= peer.config.max_retries {
+                return;
+            }
+
+            if let Some(addr) = peer.connection(to) {
+                peer.requests_retried += 1;
+                peer.send_reconcile_request(ctx, to, &addr, attempt + 1);
+            }
+        });
+    }
+
+    /// Runs `f` with a random number generator seeded from this peer's seed.
+    pub fn with_rng<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut XorShiftRng) -> R,
+    {
+        let mut rng = XorShiftRng::seed_from_u64(self.seed);
+
+        let result = f(&mut rng);
+        self.seed = rng.gen();
+
+        result
+    }
+
+    /// Returns outbound peers in random order.
+    pub fn shuffled_outbound(&mut self) -> Vec<(PeerId, Addr<Peer>)> {
+        let mut peers = self
+            .outbound
+            .iter()
+            .map(|(id, addr)| (*id, addr.clone()))
+            .collect::<Vec<_>>();
+        self.with_rng(|rng| peers.shuffle(rng));
+
+        peers
+    }
+
+    /// Schedules `RelayStrategy::on_timer` to be called after simulated `delay`.
+    pub fn schedule_timer(&mut self, ctx: &mut Context<Self>, delay: Duration) {
+        ctx.run_later(self.config.clock.real(delay), |peer, ctx| {
+            peer.with_strategy(ctx, |strategy, peer, ctx| strategy.on_timer(peer, ctx))
+        });
+    }
 
-        // Don't connect to self
-        if msg.from_id == self.id {
+    /// Records misbehaviour of peer `id`, and disconnects from it once its score
+    /// reaches `DISCOURAGEMENT_THRESHOLD`.
+    pub fn misbehaving(&mut self, id: PeerId, misbehaviour: Misbehaviour) {
+        *self.misbehaviour.entry(id).or_default() += misbehaviour.score();
+        if !self.is_discouraged(id) {
             return;
         }
 
-        // Don't connect if already connected
-        if self.is_connected_to(msg.from_id) {
-            return;
+        if let Some(addr) = self.connection(id) {
+            self.outbound.remove(&id);
+            self.inbound.remove(&id);
+            self.known_peers.remove(&id);
+            self.pending_reconciliations.remove(&id);
+            self.peers_disconnected += 1;
+
+            let disconnect = Disconnect { from_id: self.id };
+            self.send(id, &addr, disconnect);
         }
+    }
 
-        // Register inbound connection
-        self.inbound.insert(msg.from_id, msg.from_addr.clone());
+    /// Whether peer `id` misbehaved enough not to be connected to.
+    pub fn is_discouraged(&self, id: PeerId) -> bool {
+        self.misbehaviour.get(&id).cloned().unwrap_or_default() >= DISCOURAGEMENT_THRESHOLD
+    }
 
-        // Connect back
-        let is_private = match msg.from_id {
-            PeerId::Private(_) => true,
-            _ => false,
-        };
+    /// Number of transactions in the mempool created by each peer.
+    fn known_origins(&self) -> HashMap<PeerId, u32> {
+        let mut origins = HashMap::new();
+        for tx in self.mempool.iter() {
+            *origins.entry(self.registry().origin(tx.id)).or_default() += 1;
+        }
 
-        //println!("{:?} -> {:?};", msg.from_id, self.id);
+        origins
+    }
 
-        if !is_private && !self.is_connected_to(msg.from_id) {
-            self.add_outbound_peer(msg.from_id, msg.from_addr.clone());
-            let connect = Connect {
-                from_addr: ctx.address(),
-                from_id: self.id,
-            };
+    /// Transaction this peer last kept for each output spent by conflicting transactions,
+    /// and the simulated time it changed, in seconds.
+    fn conflict_versions(&self) -> HashMap<TxId, (TxId, f64)> {
+        self.registry()
+            .conflicts()
+            .keys()
+            .filter_map(|outpoint| {
+                let (id, since) = self.versions.get(outpoint)?;
+                Some((*outpoint, (*id, since.as_secs_f64())))
+            })
+            .collect()
+    }
 
-            self.bytes_sent += connect.size_bytes();
-            msg.from_addr.do_send(connect);
+    /// Whether this peer's role lets it relay transaction `tx` to others.
+    pub fn relays(&self, tx: &Tx) -> bool {
+        match &self.role {
+            Role::BlackHole | Role::CollidingTx => false,
+            Role::SelectiveRelay(origins) | Role::Eclipse(origins) => {
+                !origins.contains(&self.registry().origin(tx.id))
+            }
+            _ => true,
         }
     }
-}
 
-impl Handler<ReconcileRequest> for Peer {
-    type Result = ();
+    /// Whether this peer's role lets it relay transaction `tx` to peer `to`,
+    /// and `tx` passes the fee filter of `to`.
+    pub fn relays_to(&self, to: PeerId, tx: &Tx) -> bool {
+        match &self.role {
+            Role::Eclipse(victims) if victims.contains(&to) => false,
+            _ => self.relays(tx) && self.passes_fee_filter(to, tx),
+        }
+    }
 
-    fn handle(&mut self, msg: ReconcileRequest, ctx: &mut Self::Context) -> Self::Result {
-        self.bytes_received += msg.size_bytes();
+    /// Whether transaction `tx` pays at least the fee rate peer `to` asked for.
+    pub fn passes_fee_filter(&self, to: PeerId, tx: &Tx) -> bool {
+        self.fee_filters
+            .get(&to)
+            .is_none_or(|fee_rate| tx.fee_rate >= *fee_rate)
+    }
 
-        if let Ok(missing) = self.reconciliation_set.reconcile_with(&msg.sketch) {
-            let rec_res = ReconcileResult {
-                from_addr: ctx.address(),
+    /// Announces the minimum fee rate of the mempool to peer `to`, if peers have one.
+    fn send_fee_filter(&mut self, to: PeerId, addr: &Addr<Peer>) {
+        if self.config.min_fee_rate.is_some() {
+            let fee_filter = FeeFilter {
                 from_id: self.id,
-                missing,
+                fee_rate: self.mempool.min_fee_rate(),
             };
-
-            self.bytes_sent += rec_res.size_bytes();
-            msg.from_addr.do_send(rec_res);
+            self.send(to, addr, fee_filter);
         }
     }
-}
 
-impl Handler<ReconcileResult> for Peer {
-    type Result = ();
+    /// Whether this peer's role lets it answer requests of peer `to` for transaction `tx`.
+    fn serves(&self, to: PeerId, tx: &Tx) -> bool {
+        self.role != Role::FreeRider && self.relays_to(to, tx)
+    }
 
-    fn handle(&mut self, msg: ReconcileResult, ctx: &mut Self::Context) -> Self::Result {
-        self.bytes_received += msg.size_bytes();
+    /// Sketch of the reconciliation set of the connection with peer `to`,
+    /// as this peer's role makes it send, if short IDs of the connection are known.
+    fn reconciliation_sketch(&mut self, to: PeerId) -> Option<Vec<u8>> {
+        let sketch = match self.role {
+            Role::GarbageSketch => self.with_rng(|rng| {
+                let mut sketch = vec![0u8; rng.gen_range(0, 1024)];
+                rng.fill(sketch.as_mut_slice());
+                sketch
+            }),
+            Role::WrongCapacitySketch => {
+                let mut rec_set = RecSet::<u64>::with_sketch(
+                    self.config.sketch,
+                    self.config.reconciliation_capacity * 2,
+                    self.config.short_id_bits,
+                );
+                for (short_id, _) in self.short_ids(to)?.iter() {
+                    rec_set.insert(short_id);
+                }
 
-        for txid in msg.missing {
-            let req_tx = TxRequest {
-                from_addr: ctx.address(),
-                from_id: self.id,
-                txid,
-            };
+                rec_set.sketch()
+            }
+            _ => {
+                let short_ids = self.short_ids(to)?;
+                self.count_short_ids(&short_ids);
+                short_ids.sketch()
+            }
+        };
+
+        Some(sketch)
+    }
+
+    /// Finds IDs of transactions in the set difference with the `sketch` of peer `from`.
+    /// Transactions only this peer has are left out unless it would serve them to `from`.
+    ///
+    /// Returns an error if the sketch can't be decoded, and `None` if this peer doesn't
+    /// answer at all, e.g. since the salt of `from` hasn't arrived yet. Records misbehaviour
+    /// of the peer if the sketch is invalid.
+    pub fn reconcile_with(
+        &mut self,
+        from: PeerId,
+        sketch: &[u8],
+    ) -> Option<Result<SetDifference<u64>, RecSetError>> {
+        match &self.role {
+            Role::FreeRider => return None,
+            Role::Eclipse(victims) if victims.contains(&from) => return None,
+            _ => {}
+        }
+
+        if self.role == Role::OversizedResult {
+            return Some(Ok(SetDifference {
+                local: vec![],
+                remote: self.with_rng(|rng| (0..OVERSIZED_RESULT_LEN).map(|_| rng.gen()).collect()),
+            }));
+        }
+
+        let mut short_ids = self.short_ids(from)?;
+        self.count_short_ids(&short_ids);
+
+        match short_ids.reconcile_with(sketch) {
+            Ok(mut diff) => {
+                self.reconciliations += 1;
+                self.set_differences += (diff.local.len() + diff.remote.len()) as u64;
+
+                diff.local.retain(|short_id| {
+                    let tx = short_ids
+                        .get(*short_id)
+                        .and_then(|id| self.mempool.get(&id));
+                    tx.is_some_and(|tx| self.serves(from, tx))
+                });
+
+                // Don't ask for transactions this peer has or removed from the mempool
+                if let Some(known) = self
+                    .known_short_ids(from)
+                    .filter(|_| !diff.remote.is_empty())
+                {
+                    diff.remote.retain(|short_id| !known.contains(short_id));
+                }
 
-            self.bytes_sent += req_tx.size_bytes();
-            msg.from_addr.do_send(req_tx);
+                Some(Ok(diff))
+            }
+            Err(RecSetError::InvalidSketch) => {
+                self.misbehaving(from, Misbehaviour::InvalidSketch);
+                None
+            }
+            Err(RecSetError::DecodeFailed) => {
+                self.reconciliations += 1;
+                self.decode_failures += 1;
+                Some(Err(RecSetError::DecodeFailed))
+            }
         }
     }
-}
 
-impl Handler<TxRequest> for Peer {
-    type Result = ();
+    /// Keeps flooding all connections with requests for random transactions.
+    fn spam_tx_requests(&mut self, ctx: &mut Context<Self>) {
+        if self.online {
+            let peers = self
+                .outbound
+                .iter()
+                .chain(self.inbound.iter())
+                .map(|(id, addr)| (*id, addr.clone()))
+                .collect::<Vec<_>>();
 
-    fn handle(&mut self, msg: TxRequest, _ctx: &mut Self::Context) -> Self::Result {
-        self.bytes_received += msg.size_bytes();
+            for (id, addr) in peers {
+                let get_txs = GetTxs {
+                    from_addr: ctx.address(),
+                    from_id: self.id,
+                    txids: self
+                        .with_rng(|rng| (0..SPAM_TX_REQUESTS_PER_SEC).map(|_| rng.gen()).collect()),
+                    short_id_bits: self.config.short_id_bits,
+                };
 
-        if let Some(tx) = self.mempool.get(&msg.txid) {
-            let tx_msg = PeerTx {
-                from: self.id,
-                data: *tx,
-            };
+                self.send(id, &addr, get_txs);
+            }
+        }
 
-            self.bytes_sent += tx_msg.size_bytes();
-            msg.from_addr.do_send(tx_msg);
+        ctx.run_later(
+            self.config.clock.real(Duration::from_secs(1)),
+            |peer, ctx| peer.spam_tx_requests(ctx),
+        );
+    }
+
+    /// Handles transaction `tx` received from peer `from` by `mechanism`.
+    fn receive_tx(&mut self, ctx: &mut Context<Self>, from: PeerId, tx: Tx, mechanism: Mechanism) {
+        // Transactions can't be validated before the transactions they spend
+        if let Some(parent) = self.registry().parent(tx.id) {
+            let known = |id| self.mempool.contains(&id) || self.confirmed.contains(&id);
+            if !known(parent) && !known(tx.id) {
+                self.receive_orphan(ctx, from, tx, parent, mechanism);
+                return;
+            }
+        }
+
+        // Don't relay nor save already processed transaction
+        if !self.accept_tx(tx) {
+            return;
+        }
+
+        self.received_txs.entry(from).or_default().push(tx.id);
+
+        if let Some(spies) = self.config.spies.as_ref().filter(|log| log.is_spy(self.id)) {
+            spies.record(tx.id, self.id, from, self.now());
+        }
+
+        let created = self.registry().created(tx.id);
+        let latency = self.now().checked_sub(created).unwrap_or_default();
+        self.latencies.push(latency.as_secs_f64());
+
+        if self.role == Role::CollidingTx {
+            self.inject_colliding_tx(from, tx);
+        }
+
+        if self.relays(&tx) {
+            self.with_strategy(ctx, |strategy, peer, ctx| {
+                strategy.on_tx_received(peer, ctx, from, tx)
+            });
         }
+
+        self.resolve_orphans(ctx, tx.id);
+    }
+
+    /// Keeps transaction `tx` received from peer `from` by `mechanism` until its missing
+    /// `parent` arrives, and requests the parent from that peer.
+    fn receive_orphan(
+        &mut self,
+        ctx: &mut Context<Self>,
+        from: PeerId,
+        tx: Tx,
+        parent: TxId,
+        mechanism: Mechanism,
+    ) {
+        if self.orphans.contains(&tx.id) {
+            return;
+        }
+
+        *self.orphans_received.entry(mechanism).or_default() += 1;
+        let evicted = self.orphans.insert(tx, parent, from, mechanism);
+        self.orphans_evicted += evicted.len() as u64;
+
+        if let Some(addr) = self.connection(from) {
+            match self.short_id(from, parent) {
+                Some(short_id) if !self.removed.contains(&parent) => {
+                    self.request_txs(ctx, from, &addr, vec![short_id], Mechanism::Request);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Processes orphans that were waiting for transaction `parent`.
+    fn resolve_orphans(&mut self, ctx: &mut Context<Self>, parent: TxId) {
+        for (tx, from, mechanism) in self.orphans.take_children(parent) {
+            self.orphans_resolved += 1;
+            self.receive_tx(ctx, from, tx, mechanism);
+        }
+    }
+
+    /// Counts a transaction request from peer `id` against the rate limit.
+    /// Returns `false` if the request is over the limit.
+    fn allow_tx_request(&mut self, id: PeerId) -> bool {
+        let second = self.now().as_secs();
+        let (window, count) = self.tx_requests.entry(id).or_insert((second, 0));
+
+        if *window != second {
+            *window = second;
+            *count = 0;
+        }
+
+        *count += 1;
+        if *count > MAX_TX_REQUESTS_PER_SEC {
+            self.misbehaving(id, Misbehaviour::TxRequestFlood);
+            return false;
+        }
+
+        true
+    }
+
+    /// Runs one of the relay strategy hooks with this peer.
+    fn with_strategy<F>(&mut self, ctx: &mut Context<Self>, f: F)
+    where
+        F: FnOnce(&mut dyn RelayStrategy, &mut Peer, &mut Context<Peer>),
+    {
+        if let Some(mut strategy) = self.strategy.take() {
+            f(strategy.as_mut(), self, ctx);
+            self.strategy = Some(strategy);
+        }
+    }
+
+    /// Adds a transaction to the mempool.
+    /// Returns `false` if the transaction was already known, or didn't make it
+    /// into the mempool.
+    fn accept_tx(&mut self, tx: Tx) -> bool {
+        let outpoint = self.registry().outpoint(tx.id);
+        if self.spent.contains(&outpoint) {
+            // Conflicts of confirmed transactions are invalid
+            if !self.confirmed.contains(&tx.id) {
+                self.forget_tx(tx.id);
+            }
+            return false;
+        }
+
+        match self.mempool.insert_spending(tx, outpoint, self.now()) {
+            Ok(inserted) => {
+                if let Some(replaced) = inserted.replaced {
+                    self.forget_tx(replaced.id);
+                    self.txs_replaced += 1 + self.remove_descendants(replaced.id) as u64;
+                }
+
+                self.txs_evicted += inserted.evicted.len() as u64;
+                for evicted in inserted.evicted {
+                    self.forget_tx(evicted.id);
+                    self.txs_evicted += self.remove_descendants(evicted.id) as u64;
+                }
+            }
+            Err(MempoolError::Duplicate) => return false,
+            Err(MempoolError::FeeTooLow) | Err(MempoolError::BelowMinFeeRate) => {
+                self.txs_rejected += 1;
+                self.forget_tx(tx.id);
+                return false;
+            }
+            Err(MempoolError::ReplacementFeeTooLow) => {
+                self.replacements_rejected += 1;
+                self.forget_tx(tx.id);
+                return false;
+            }
+        }
+        self.versions.insert(outpoint, (tx.id, self.now()));
+        self.removed.remove(&tx.id);
+
+        let filtered = self
+            .outbound
+            .keys()
+            .chain(self.inbound.keys())
+            .filter(|id| !self.passes_fee_filter(**id, &tx))
+            .count();
+        self.txs_filtered += filtered as u64;
+
+        true
+    }
+
+    /// Removes a transaction from the mempool, e.g. once it's confirmed.
+    pub fn remove_tx(&mut self, id: TxId) -> Option<Tx> {
+        let tx = self.mempool.remove(&id)?;
+        self.forget_tx(id);
+
+        Some(tx)
+    }
+
+    /// Removes transactions that spend transaction `id`, which left the mempool
+    /// without being confirmed, along with their own descendants.
+    ///
+    /// Returns the number of removed transactions.
+    fn remove_descendants(&mut self, id: TxId) -> usize {
+        let registry = self.config.registry.clone();
+        let children = self
+            .mempool
+            .iter()
+            .filter(|tx| registry.parent(tx.id) == Some(id))
+            .map(|tx| tx.id)
+            .collect::<Vec<_>>();
+
+        let mut removed = 0;
+        for child in children {
+            if self.remove_tx(child).is_some() {
+                removed += 1 + self.remove_descendants(child);
+            }
+        }
+
+        removed
+    }
+
+    /// Remembers that transaction `id` left the mempool, so it isn't requested again.
+    fn forget_tx(&mut self, id: TxId) {
+        if self.removed.insert(id) {
+            self.removed_order.push_back(id);
+        }
+
+        while self.removed_order.len() > MAX_REMOVED_TXS {
+            if let Some(oldest) = self.removed_order.pop_front() {
+                self.removed.remove(&oldest);
+            }
+        }
+    }
+
+    /// Keeps mining blocks on a Poisson schedule while the peer is online.
+    fn schedule_mining(&mut self, ctx: &mut Context<Self>, mining: MiningConfig) {
+        let delay = self.with_rng(|rng| {
+            ValueDistribution::Exponential(mining.miner_interval()).sample_duration(rng)
+        });
+        ctx.run_later(self.config.clock.real(delay), move |peer, ctx| {
+            if peer.online {
+                peer.mine_block(ctx, &mining);
+            }
+
+            peer.schedule_mining(ctx, mining);
+        });
+    }
+
+    /// Assembles a block of the best paying transactions in the mempool and relays it.
+    fn mine_block(&mut self, ctx: &mut Context<Self>, mining: &MiningConfig) {
+        let id = BlockId {
+            miner: self.id,
+            number: self.blocks_mined,
+        };
+        self.blocks_mined += 1;
+
+        let registry = self.config.registry.clone();
+        let block = Block::assemble(
+            id,
+            self.now(),
+            &self.mempool,
+            mining.max_block_weight,
+            |id| registry.parent(id),
+        );
+        self.receive_block(ctx, self.id, Arc::new(block));
+    }
+
+    /// Handles block `block` received from peer `from`, or mined by this peer:
+    /// removes the transactions it confirms and relays it to all other connections.
+    fn receive_block(&mut self, ctx: &mut Context<Self>, from: PeerId, block: Arc<Block>) {
+        if self.blocks.contains_key(&block.id) {
+            return;
+        }
+        self.blocks.insert(block.id, block.clone());
+        self.pending_blocks.remove(&block.id);
+
+        if from != self.id {
+            let latency = self.now().checked_sub(block.created).unwrap_or_default();
+            self.block_latencies.push(latency.as_secs_f64());
+        }
+
+        for tx in block.txs.iter() {
+            if self.confirmed.insert(tx.id) && self.remove_tx(tx.id).is_none() {
+                // Don't request confirmed transactions this peer hasn't seen yet
+                self.forget_tx(tx.id);
+            }
+
+            // Conflicting transactions lose to the confirmed one
+            let outpoint = self.registry().outpoint(tx.id);
+            self.spent.insert(outpoint);
+            if let Some(conflict) = self.mempool.spender(outpoint) {
+                self.remove_tx(conflict);
+                self.txs_replaced += 1 + self.remove_descendants(conflict) as u64;
+            }
+            if self.versions.get(&outpoint).map(|(id, _)| *id) != Some(tx.id) {
+                self.versions.insert(outpoint, (tx.id, self.now()));
+                self.removed.remove(&tx.id);
+            }
+        }
+        for tx in block.txs.iter() {
+            self.resolve_orphans(ctx, tx.id);
+        }
+
+        if self.role == Role::BlackHole {
+            return;
+        }
+
+        let peers = self
+            .outbound
+            .iter()
+            .chain(self.inbound.iter())
+            .filter(|(id, _)| **id != from)
+            .filter(|(id, _)| !matches!(&self.role, Role::Eclipse(victims) if victims.contains(id)))
+            .map(|(id, addr)| (*id, addr.clone()))
+            .collect::<Vec<_>>();
+
+        if !self.config.compact_blocks {
+            for (id, addr) in peers {
+                let msg = PeerBlock {
+                    from: self.id,
+                    block: block.clone(),
+                };
+                self.send(id, &addr, msg);
+            }
+            return;
+        }
+
+        let nonce = self.with_rng(|rng| rng.gen());
+        let key = compact_block::block_key(block.id, nonce);
+        let short_ids = block
+            .txs
+            .iter()
+            .map(|tx| compact_block::short_id(self.registry(), tx.id, key))
+            .collect::<Vec<_>>();
+        for (id, addr) in peers {
+            let msg = CompactBlock {
+                from_addr: ctx.address(),
+                from_id: self.id,
+                nonce,
+                short_ids: short_ids.clone(),
+                block: block.clone(),
+            };
+            self.send(id, &addr, msg);
+        }
+    }
+
+    /// Removes expired transactions from the mempool every simulated second.
+    fn schedule_expiry(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_later(
+            self.config.clock.real(Duration::from_secs(1)),
+            |peer, ctx| {
+                for tx in peer.mempool.expire(peer.now()) {
+                    peer.txs_expired += 1;
+                    peer.forget_tx(tx.id);
+                    peer.txs_expired += peer.remove_descendants(tx.id) as u64;
+                }
+
+                peer.schedule_expiry(ctx);
+            },
+        );
+    }
+
+    /// Creates a transaction with the same unsalted short ID as `target` and floods
+    /// it to all connections except peer `from`.
+    fn inject_colliding_tx(&mut self, from: PeerId, target: Tx) {
+        let short_id = self.registry().short_id(target.id, ShortIdKey::UNSALTED);
+        if !self.collided.insert(short_id) {
+            return;
+        }
+
+        let mut body = vec![0u8; target.size as usize];
+        self.with_rng(|rng| rng.fill(body.as_mut_slice()));
+        let tx = self
+            .config
+            .registry
+            .register_colliding(body, self.id, self.now(), target.id);
+        self.accept_tx(tx);
+
+        let peers = self
+            .outbound
+            .iter()
+            .chain(self.inbound.iter())
+            .filter(|(id, _)| **id != from)
+            .map(|(id, addr)| (*id, addr.clone()))
+            .collect::<Vec<_>>();
+
+        for (id, addr) in peers {
+            let peer_tx = PeerTx {
+                from: self.id,
+                data: tx,
+            };
+
+            self.send(id, &addr, peer_tx);
+        }
+    }
+
+    /// Creates a new transaction and announces it to a single random outbound peer.
+    fn create_tx(&mut self) {
+        let mut tx_data = vec![0u8; 1024];
+        self.with_rng(|rng| rng.fill(tx_data.as_mut_slice()));
+
+        // The previous transaction may be replaced or spent while it's unconfirmed
+        let last = self
+            .last_created
+            .and_then(|id| self.mempool.get(&id).cloned());
+        let replaced = match (self.config.replace_share, last) {
+            (Some(share), Some(last)) if self.with_rng(|rng| rng.gen_bool(share)) => Some(last),
+            _ => None,
+        };
+        let parent = match (self.config.child_share, last) {
+            (Some(share), Some(last))
+                if replaced.is_none() && self.with_rng(|rng| rng.gen_bool(share)) =>
+            {
+                Some(last.id)
+            }
+            _ => None,
+        };
+
+        let registry = self.config.registry.clone();
+        let mut tx = match (replaced, parent) {
+            (Some(replaced), _) => {
+                registry.register_conflicting(tx_data, self.id, self.now(), replaced.id)
+            }
+            (None, Some(parent)) => registry.register_child(tx_data, self.id, self.now(), parent),
+            (None, None) => registry.register(tx_data, self.id, self.now()),
+        };
+        self.last_created = Some(tx.id);
+        match (replaced, self.config.fee_rate) {
+            // Replacements pay just enough to replace the original
+            (Some(replaced), _) => tx.fee_rate = replaced.fee_rate + INCREMENTAL_RELAY_FEE_RATE,
+            (None, Some(fee_rate)) => {
+                tx.fee_rate = self.with_rng(|rng| fee_rate.sample(rng).round() as u32)
+            }
+            (None, None) => {}
+        }
+
+        // Transactions that don't make it into the own mempool aren't broadcast
+        if !self.accept_tx(tx) {
+            return;
+        }
+
+        let first = self
+            .outbound
+            .iter()
+            .find(|(id, _)| self.passes_fee_filter(**id, &tx))
+            .map(|(id, addr)| (*id, addr.clone()));
+        if let Some((id, addr)) = first.clone() {
+            let peer_tx = PeerTx {
+                from: self.id,
+                data: tx,
+            };
+
+            self.send(id, &addr, peer_tx);
+        }
+
+        if let Some(share) = self.config.double_spend_share {
+            if self.with_rng(|rng| rng.gen_bool(share)) {
+                self.request_double_spend(tx, first.map(|(id, _)| id));
+            }
+        }
+    }
+
+    /// Makes a random known peer other than `except` double-spend transaction `tx`.
+    fn request_double_spend(&mut self, tx: Tx, except: Option<PeerId>) {
+        let mut peers = self
+            .known_peers
+            .keys()
+            .filter(|id| Some(**id) != except)
+            .cloned()
+            .collect::<Vec<_>>();
+        peers.sort();
+
+        if let Some(id) = self.with_rng(|rng| peers.choose(rng).cloned()) {
+            self.known_peers[&id].do_send(DoubleSpend { original: tx });
+        }
+    }
+
+    /// Keeps creating transactions at `tx_interval` while the peer is online.
+    fn schedule_tx_creation(&mut self, ctx: &mut Context<Self>) {
+        if let Some(interval) = self.config.tx_interval {
+            let delay = self.with_rng(|rng| interval.sample_duration(rng));
+            ctx.run_later(self.config.clock.real(delay), |peer, ctx| {
+                if peer.online {
+                    peer.create_tx();
+                }
+
+                peer.schedule_tx_creation(ctx);
+            });
+        }
+    }
+
+    /// Connects to a known peer.
+    ///
+    /// Private peers make an outbound connection right away, public peers
+    /// wait for the other side to connect back.
+    fn connect_to(&mut self, ctx: &mut Context<Self>, id: PeerId, addr: Addr<Peer>) {
+        if !self.is_public() {
+            let salt = self.salts.get(&id).cloned().unwrap_or_default();
+            self.add_outbound_peer(id, addr.clone(), salt);
+        }
+
+        let connect = Connect {
+            from_addr: ctx.address(),
+            from_id: self.id,
+            salt: self.salt,
+        };
+        self.send(id, &addr, connect);
+        self.send_fee_filter(id, &addr);
+
+        if !self.is_public() {
+            self.with_strategy(ctx, |strategy, peer, ctx| {
+                strategy.on_peer_connected(peer, ctx, id)
+            });
+        }
+    }
+
+    /// Drops all connections and schedules coming back online.
+    fn go_offline(&mut self, ctx: &mut Context<Self>, churn: ChurnConfig) {
+        let mut peers = self.outbound.clone();
+        peers.extend(self.inbound.drain());
+        self.outbound.clear();
+
+        for (id, addr) in peers {
+            let disconnect = Disconnect { from_id: self.id };
+            self.send(id, &addr, disconnect);
+        }
+
+        self.online = false;
+        self.pending_reconciliations.clear();
+
+        let downtime = self.with_rng(|rng| churn.downtime.sample_duration(rng));
+        ctx.run_later(self.config.clock.real(downtime), move |peer, ctx| {
+            peer.go_online(ctx, churn)
+        });
+    }
+
+    /// Reconnects to known peers and schedules going offline again.
+    fn go_online(&mut self, ctx: &mut Context<Self>, churn: ChurnConfig) {
+        self.online = true;
+
+        // Private peers reconnect to public ones. Public peers also invite
+        // private peers that were connected to them before.
+        let known_peers = self
+            .known_peers
+            .iter()
+            .filter(|(id, _)| self.is_public() || matches!(id, PeerId::Public(_)))
+            .map(|(id, addr)| (*id, addr.clone()))
+            .collect::<Vec<_>>();
+
+        for (id, addr) in known_peers {
+            self.connect_to(ctx, id, addr);
+        }
+
+        self.schedule_going_offline(ctx, churn);
+    }
+
+    fn schedule_going_offline(&mut self, ctx: &mut Context<Self>, churn: ChurnConfig) {
+        let session = self.with_rng(|rng| churn.session.sample_duration(rng));
+        ctx.run_later(self.config.clock.real(session), move |peer, ctx| {
+            peer.go_offline(ctx, churn)
+        });
+    }
+}
+
+/// Make actor from `Peer`
+impl Actor for Peer {
+    type Context = actix::Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(min_fee_rate) = self.config.min_fee_rate {
+            let fee_rate = self.with_rng(|rng| min_fee_rate.sample(rng).round() as u32);
+            self.mempool.set_min_fee_rate(fee_rate);
+
+            // Connections of private peers are set up before they start
+            let outbound = self
+                .outbound
+                .iter()
+                .map(|(id, addr)| (*id, addr.clone()))
+                .collect::<Vec<_>>();
+            for (id, addr) in outbound {
+                self.send_fee_filter(id, &addr);
+            }
+        }
+
+        if self.config.mempool.expiry.is_some() {
+            self.schedule_expiry(ctx);
+        }
+
+        if let Some(mining) = self.config.mining.clone() {
+            if mining.miners.contains(&self.id) {
+                self.schedule_mining(ctx, mining);
+            }
+        }
+
+        ctx.run_later(Duration::from_secs(0), |act, ctx| {
+            if !act.is_public() {
+                act.create_tx();
+                act.schedule_tx_creation(ctx);
+            }
+        });
+
+        let report_delay = Duration::from_secs(self.config.traffic_timeout.saturating_sub(1));
+        ctx.run_later(self.config.clock.real(report_delay), |peer, _ct| {
+            let traffic_msg = TrafficReport {
+                from_id: peer.id,
+                bytes_sent: peer.bytes_sent,
+                bytes_received: peer.bytes_received,
+                messages_dropped: peer.messages_dropped,
+                bytes_dropped: peer.bytes_dropped,
+                requests_retried: peer.requests_retried,
+                peers_disconnected: peer.peers_disconnected,
+                short_ids_added: peer.short_ids_added,
+                short_id_collisions: peer.short_id_collisions,
+                sketches_sent: peer.sketches_sent,
+                sketch_bytes: peer.sketch_bytes,
+                reconciliations: peer.reconciliations,
+                decode_failures: peer.decode_failures,
+                set_differences: peer.set_differences,
+                misbehaviour: peer.misbehaviour.clone(),
+                latencies: peer.latencies.clone(),
+                known_origins: peer.known_origins(),
+                mempool_size: peer.mempool.len() as u64,
+                txs_evicted: peer.txs_evicted,
+                txs_expired: peer.txs_expired,
+                txs_rejected: peer.txs_rejected,
+                blocks_mined: u64::from(peer.blocks_mined),
+                txs_confirmed: peer.confirmed.len() as u64,
+                block_latencies: peer.block_latencies.clone(),
+                compact_blocks: peer.compact_blocks,
+                compact_blocks_reconstructed: peer.compact_blocks_reconstructed,
+                compact_block_failures: peer.compact_block_failures,
+                block_round_trips: peer.block_round_trips,
+                block_extra_bytes: peer.block_extra_bytes,
+                txs_filtered: peer.txs_filtered,
+                orphans: std::mem::take(&mut peer.orphans_received),
+                orphans_resolved: peer.orphans_resolved,
+                orphans_evicted: peer.orphans_evicted,
+                txs_replaced: peer.txs_replaced,
+                replacements_rejected: peer.replacements_rejected,
+                conflict_versions: peer.conflict_versions(),
+            };
+
+            peer.config.traffic_counter_addr.do_send(traffic_msg);
+        });
+
+        if let Some(churn) = self.config.churn {
+            self.schedule_going_offline(ctx, churn);
+        }
+
+        if self.role == Role::TxRequestSpam {
+            self.spam_tx_requests(ctx);
+        }
+
+        self.with_strategy(ctx, |strategy, peer, ctx| strategy.on_started(peer, ctx));
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        Running::Stop
+    }
+}
+
+impl Handler<PeerTx> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: PeerTx, ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+        self.trace_tx(msg.from, msg.data, Mechanism::Flood);
+        self.receive_tx(ctx, msg.from, msg.data, Mechanism::Flood);
+    }
+}
+
+impl Handler<Txs> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Txs, ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+        let from = msg.from;
+        for tx in msg.txs {
+            // Transactions nobody asked for were pushed after a reconciliation
+            let mechanism = self
+                .short_id(from, tx.id)
+                .and_then(|short_id| self.requested_via.remove(&(from, short_id)))
+                .unwrap_or(Mechanism::Reconciliation);
+            self.trace_tx(from, tx, mechanism);
+
+            self.receive_tx(ctx, from, tx, mechanism);
+        }
+    }
+}
+
+impl Handler<DoubleSpend> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: DoubleSpend, ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        let mut body = vec![0u8; msg.original.size as usize];
+        self.with_rng(|rng| rng.fill(body.as_mut_slice()));
+        let mut tx =
+            self.config
+                .registry
+                .register_conflicting(body, self.id, self.now(), msg.original.id);
+        tx.fee_rate = msg.original.fee_rate;
+
+        // The double spend is relayed as if this peer received it
+        let id = self.id;
+        if self.accept_tx(tx) && self.relays(&tx) {
+            self.with_strategy(ctx, |strategy, peer, ctx| {
+                strategy.on_tx_received(peer, ctx, id, tx)
+            });
+        }
+    }
+}
+
+impl Handler<FeeFilter> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: FeeFilter, _ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+
+        // Transactions below the filter are left out of the peer's sketches from now on
+        self.fee_filters.insert(msg.from_id, msg.fee_rate);
+    }
+}
+
+impl Handler<PeerBlock> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: PeerBlock, ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+        self.receive_block(ctx, msg.from, msg.block);
+    }
+}
+
+impl Handler<CompactBlock> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: CompactBlock, ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+
+        let id = msg.block.id;
+        if self.blocks.contains_key(&id) || self.pending_blocks.contains_key(&id) {
+            return;
+        }
+
+        self.compact_blocks += 1;
+        let key = compact_block::block_key(id, msg.nonce);
+        let txs = self.mempool.iter().map(|tx| tx.id).collect::<Vec<_>>();
+        let index = compact_block::mempool_index(self.registry(), txs, key);
+        let indexes = match compact_block::reconstruct(&msg.block, &msg.short_ids, &index) {
+            Reconstruction::Complete => {
+                self.compact_blocks_reconstructed += 1;
+                self.receive_block(ctx, msg.from_id, msg.block);
+                return;
+            }
+            Reconstruction::Missing(indexes) => indexes,
+            Reconstruction::Failed => {
+                self.compact_block_failures += 1;
+                (0..msg.block.txs.len()).collect()
+            }
+        };
+
+        self.pending_blocks.insert(id, msg.block);
+        self.block_round_trips += 1;
+
+        let request = GetBlockTxn {
+            from_addr: ctx.address(),
+            from_id: self.id,
+            block_id: id,
+            indexes,
+        };
+        self.block_extra_bytes += request.size_bytes();
+        self.send(msg.from_id, &msg.from_addr, request);
+    }
+}
+
+impl Handler<GetBlockTxn> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: GetBlockTxn, _ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+
+        let block = match self.blocks.get(&msg.block_id) {
+            Some(block) => block.clone(),
+            None => return,
+        };
+        let txs = msg
+            .indexes
+            .iter()
+            .filter_map(|index| block.txs.get(*index).cloned())
+            .collect();
+        let response = BlockTxn {
+            from: self.id,
+            block_id: msg.block_id,
+            txs,
+        };
+        self.send(msg.from_id, &msg.from_addr, response);
+    }
+}
+
+impl Handler<BlockTxn> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: BlockTxn, ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        let size = msg.size_bytes();
+        self.bytes_received += size;
+
+        if let Some(block) = self.pending_blocks.remove(&msg.block_id) {
+            self.block_extra_bytes += size;
+            self.receive_block(ctx, msg.from, block);
+        }
+    }
+}
+
+impl Handler<Connect> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Connect, ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+
+        // Don't connect to self, or to peers that misbehaved
+        if msg.from_id == self.id || self.is_discouraged(msg.from_id) {
+            return;
+        }
+
+        let is_private = matches!(msg.from_id, PeerId::Private(_));
+        self.salts.insert(msg.from_id, msg.salt);
+
+        // Private peers don't accept inbound connections. A public peer that came
+        // back online invites them to connect to it again instead.
+        if !self.is_public() {
+            if !is_private {
+                let is_new = !self.is_connected_to(msg.from_id);
+                self.add_outbound_peer(msg.from_id, msg.from_addr.clone(), msg.salt);
+
+                let connect = Connect {
+                    from_addr: ctx.address(),
+                    from_id: self.id,
+                    salt: self.salt,
+                };
+                self.send(msg.from_id, &msg.from_addr, connect);
+                self.send_fee_filter(msg.from_id, &msg.from_addr);
+
+                if is_new {
+                    self.with_strategy(ctx, |strategy, peer, ctx| {
+                        strategy.on_peer_connected(peer, ctx, msg.from_id)
+                    });
+                }
+            }
+
+            return;
+        }
+
+        // Don't connect if already connected
+        if self.is_connected_to(msg.from_id) {
+            return;
+        }
+
+        // Register inbound connection
+        self.inbound.insert(msg.from_id, msg.from_addr.clone());
+        self.known_peers.insert(msg.from_id, msg.from_addr.clone());
+        self.send_fee_filter(msg.from_id, &msg.from_addr);
+
+        // Connect back
+        if !is_private && !self.is_connected_to(msg.from_id) {
+            self.add_outbound_peer(msg.from_id, msg.from_addr.clone(), msg.salt);
+            let connect = Connect {
+                from_addr: ctx.address(),
+                from_id: self.id,
+                salt: self.salt,
+            };
+
+            self.send(msg.from_id, &msg.from_addr, connect);
+        }
+
+        self.with_strategy(ctx, |strategy, peer, ctx| {
+            strategy.on_peer_connected(peer, ctx, msg.from_id)
+        });
+    }
+}
+
+impl Handler<Disconnect> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, _ctx: &mut Context<Self>) {
+        if !self.online {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+
+        self.outbound.remove(&msg.from_id);
+        self.inbound.remove(&msg.from_id);
+    }
+}
+
+impl Handler<ReconcileRequest> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReconcileRequest, ctx: &mut Self::Context) -> Self::Result {
+        if !self.online || self.is_discouraged(msg.from_id) {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+
+        self.with_strategy(ctx, |strategy, peer, ctx| {
+            strategy.on_protocol_message(peer, ctx, ProtocolMessage::ReconcileRequest(msg))
+        });
+    }
+}
+
+impl Handler<ReconcileResult> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReconcileResult, ctx: &mut Self::Context) -> Self::Result {
+        if !self.online || self.is_discouraged(msg.from_id) {
+            return;
+        }
+
+        // Any result settles the request, even a failed one: sending the same sketch
+        // again wouldn't decode any better
+        self.bytes_received += msg.size_bytes();
+        self.pending_reconciliations.remove(&msg.from_id);
+
+        // A sketch can't decode more differences than its capacity
+        if msg.missing.len() > self.config.reconciliation_capacity {
+            self.misbehaving(msg.from_id, Misbehaviour::OversizedResult);
+            return;
+        }
+
+        self.with_strategy(ctx, |strategy, peer, ctx| {
+            strategy.on_protocol_message(peer, ctx, ProtocolMessage::ReconcileResult(msg))
+        });
+    }
+}
+
+impl Handler<GetTxs> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: GetTxs, _ctx: &mut Self::Context) -> Self::Result {
+        // Requests of disconnected peers that were on the way are dropped with the connection
+        if !self.online || self.is_discouraged(msg.from_id) {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+
+        // Every requested transaction counts against the rate limit
+        let from = msg.from_id;
+        let allowed = msg
+            .txids
+            .into_iter()
+            .filter(|_| self.allow_tx_request(from))
+            .collect::<Vec<_>>();
+
+        self.push_txs(msg.from_id, &msg.from_addr, allowed);
+    }
+}
+
+impl Handler<Inv> for Peer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Inv, ctx: &mut Self::Context) -> Self::Result {
+        if !self.online {
+            return;
+        }
+
+        self.bytes_received += msg.size_bytes();
+
+        // Request announced transactions that aren't known yet
+        let known = match self.known_short_ids(msg.from_id) {
+            Some(known) => known,
+            None => return,
+        };
+        let unknown = msg
+            .txids
+            .into_iter()
+            .filter(|txid| !known.contains(txid))
+            .collect();
+
+        self.request_txs(ctx, msg.from_id, &msg.from_addr, unknown, msg.mechanism);
+    }
+}
+
+impl<M> Handler<Delayed<M>> for Peer
+where
+    M: Message<Result = ()> + Send + 'static,
+    Peer: Handler<M>,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: Delayed<M>, ctx: &mut Self::Context) -> Self::Result {
+        ctx.notify_later(msg.msg, msg.delay);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Peer, PeerId, MAX_REMOVED_TXS};
+    use crate::faults::parse_cut_link;
+    use crate::mempool::DEFAULT_FEE_RATE;
+    use crate::messages::{Connect, Inv, ReconcileRequest, ReconcileResult};
+    use crate::recset::truncate_short_id;
+    use crate::relay::RelayKind;
+    use crate::short_ids::ShortIdKey;
+    use crate::simulation::SimulationBuilder;
+    use crate::trace::Mechanism;
+    use crate::tx_registry::{TxId, TxRegistry};
+    use actix::prelude::*;
+    use std::alloc::{GlobalAlloc, Layout, System as SystemAlloc};
+    use std::cell::Cell;
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    /// Counts bytes each thread allocated and didn't free yet, so tests can measure
+    /// the memory of what they build.
+    struct CountingAllocator;
+
+    thread_local! {
+        static LIVE_BYTES: Cell<isize> = const { Cell::new(0) };
+    }
+
+    fn count_bytes(delta: isize) {
+        let _ = LIVE_BYTES.try_with(|bytes| bytes.set(bytes.get() + delta));
+    }
+
+    fn live_bytes() -> isize {
+        LIVE_BYTES.with(|bytes| bytes.get())
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count_bytes(layout.size() as isize);
+            SystemAlloc.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            count_bytes(-(layout.size() as isize));
+            SystemAlloc.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count_bytes(new_size as isize - layout.size() as isize);
+            SystemAlloc.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Bytes a peer with `connections` connections takes per transaction in its mempool,
+    /// after building sketches for all of them.
+    fn bytes_per_tx(connections: u32) -> isize {
+        const TXS: u32 = 10_000;
+
+        let (results_tx, _results_rx) = mpsc::channel();
+        let (bytes_tx, bytes_rx) = mpsc::channel();
+        let _ = System::run(move || {
+            let registry = Arc::new(TxRegistry::new());
+            let txs = (0..TXS)
+                .map(|i| {
+                    let body = i.to_le_bytes().to_vec();
+                    registry.register(body, PeerId::Private(0), Duration::from_secs(0))
+                })
+                .collect::<Vec<_>>();
+
+            let config = SimulationBuilder::new().peer_config(registry, results_tx);
+            let other = Peer::new(PeerId::Public(1), RelayKind::Erlay.create(), config.clone());
+            let addr = other.start();
+            let mut peer = Peer::new(PeerId::Public(0), RelayKind::Erlay.create(), config);
+            for i in 0..connections {
+                peer.add_outbound_peer(PeerId::Private(i), addr.clone(), u64::from(i));
+            }
+
+            let before = live_bytes();
+            for tx in txs {
+                assert!(peer.accept_tx(tx));
+            }
+            for i in 0..connections {
+                assert!(peer.reconciliation_sketch(PeerId::Private(i)).is_some());
+            }
+            let _ = bytes_tx.send((live_bytes() - before) / TXS as isize);
+
+            System::current().stop();
+        });
+
+        bytes_rx.recv().expect("Bytes per transaction")
+    }
+
+    #[test]
+    pub fn test_reconciliation_before_connect() {
+        let (results_tx, _results_rx) = mpsc::channel();
+        let (checked_tx, checked_rx) = mpsc::channel();
+        let _ = System::run(move || {
+            let registry = Arc::new(TxRegistry::new());
+            let tx = registry.register(vec![0], PeerId::Private(0), Duration::from_secs(0));
+
+            let config = SimulationBuilder::new().peer_config(registry, results_tx);
+            let mut public =
+                Peer::new(PeerId::Public(0), RelayKind::Erlay.create(), config.clone());
+            let mut private = Peer::new(
+                PeerId::Private(0),
+                RelayKind::Erlay.create(),
+                config.clone(),
+            );
+            let addr = Peer::new(PeerId::Public(1), RelayKind::Erlay.create(), config).start();
+            private.add_outbound_peer(public.id, addr, public.salt);
+            assert!(public.accept_tx(tx));
+            assert!(private.accept_tx(tx));
+
+            // The request arrives before the Connect carrying the requester's salt
+            let sketch = private.reconciliation_sketch(public.id).expect("Sketch");
+            assert!(public.reconcile_with(private.id, &sketch).is_none());
+
+            // Once it's known, both sides agree on the short ID of the shared transaction
+            public.salts.insert(private.id, private.salt);
+            let diff = public.reconcile_with(private.id, &sketch).expect("Answer");
+            let diff = diff.expect("Decoded sketch");
+            assert!(diff.local.is_empty());
+            assert!(diff.remote.is_empty());
+            let _ = checked_tx.send(());
+
+            System::current().stop();
+        });
+
+        checked_rx.recv().expect("Reconciliation checked");
+    }
+
+    #[test]
+    pub fn test_misbehaving_peers_disconnected() {
+        let (results_tx, results_rx) = mpsc::channel();
+        let _ = System::run(move || {
+            let config = SimulationBuilder::new()
+                .time_scale(10.0)
+                .duration(Some(3))
+                .peer_config(Arc::new(TxRegistry::new()), results_tx);
+            let capacity = config.reconciliation_capacity;
+            let start = |id| Peer::new(id, RelayKind::Erlay.create(), config.clone()).start();
+            let honest = start(PeerId::Public(0));
+            let (garbage, oversized) = (PeerId::Private(0), PeerId::Public(1));
+            let (garbage_addr, oversized_addr) = (start(garbage), start(oversized));
+
+            for (id, addr) in [(garbage, &garbage_addr), (oversized, &oversized_addr)].iter() {
+                honest.do_send(Connect {
+                    from_addr: (*addr).clone(),
+                    from_id: *id,
+                    salt: 1,
+                });
+            }
+
+            // Both are sent twice, the second time after the connection is dropped
+            for _ in 0..2 {
+                honest.do_send(ReconcileRequest {
+                    from_addr: garbage_addr.clone(),
+                    from_id: garbage,
+                    sketch: vec![1, 2, 3],
+                });
+                honest.do_send(ReconcileResult {
+                    from_addr: oversized_addr.clone(),
+                    from_id: oversized,
+                    success: true,
+                    missing: (1..=capacity as u64 + 1).collect(),
+                    short_id_bits: 64,
+                });
+            }
+        });
+
+        let results = results_rx.recv().expect("Simulation results");
+        assert_eq!(results.misbehaviour[&PeerId::Private(0)], 100);
+        assert_eq!(results.misbehaviour[&PeerId::Public(1)], 100);
+        assert_eq!(results.traffic[&PeerId::Public(0)].peers_disconnected, 2);
+        assert_eq!(results.peers_disconnected(), 2);
+    }
+
+    #[test]
+    pub fn test_lost_tx_request_retried() {
+        let (results_tx, results_rx) = mpsc::channel();
+        let _ = System::run(move || {
+            let registry = Arc::new(TxRegistry::new());
+            let tx = registry.register(vec![0], PeerId::Public(0), Duration::from_secs(0));
+            let key_registry = registry.clone();
+
+            // The first request is lost, a retry after the link comes back gets through
+            let config = SimulationBuilder::new()
+                .time_scale(10.0)
+                .duration(Some(5))
+                .fault(parse_cut_link("pub0-pub1@0..1").unwrap())
+                .peer_config(registry, results_tx);
+            let mut holder = Peer::new(
+                PeerId::Public(0),
+                RelayKind::Flooding.create(),
+                config.clone(),
+            );
+            let requester = Peer::new(PeerId::Public(1), RelayKind::Flooding.create(), config);
+            assert!(holder.accept_tx(tx));
+            let (holder_id, holder_salt) = (holder.id, holder.salt);
+            let (requester_id, requester_salt) = (requester.id, requester.salt);
+            let holder = holder.start();
+            let requester = requester.start();
+
+            holder.do_send(Connect {
+                from_addr: requester.clone(),
+                from_id: requester_id,
+                salt: requester_salt,
+            });
+            requester.do_send(Connect {
+                from_addr: holder.clone(),
+                from_id: holder_id,
+                salt: holder_salt,
+            });
+
+            let key = ShortIdKey::from_salts(holder_salt, requester_salt);
+            requester.do_send(Inv {
+                from_addr: holder,
+                from_id: holder_id,
+                txids: vec![truncate_short_id(key_registry.short_id(tx.id, key), 64)],
+                short_id_bits: 64,
+                mechanism: Mechanism::Request,
+            });
+        });
+
+        let results = results_rx.recv().expect("Simulation results");
+        let requester = &results.traffic[&PeerId::Public(1)];
+        assert_eq!(requester.requests_retried, 1);
+        assert_eq!(results.mempool_sizes[&PeerId::Public(1)], 1);
+    }
+
+    #[test]
+    pub fn test_fee_filter_excludes_txs() {
+        let (results_tx, _results_rx) = mpsc::channel();
+        let (checked_tx, checked_rx) = mpsc::channel();
+        let _ = System::run(move || {
+            let registry = Arc::new(TxRegistry::new());
+            let mut cheap = registry.register(vec![0], PeerId::Public(0), Duration::from_secs(0));
+            cheap.fee_rate = DEFAULT_FEE_RATE / 2;
+            let paying = registry.register(vec![1], PeerId::Public(0), Duration::from_secs(0));
+
+            let config = SimulationBuilder::new().peer_config(registry, results_tx);
+            let other = Peer::new(PeerId::Public(1), RelayKind::Erlay.create(), config.clone());
+            let addr = other.start();
+            let mut peer = Peer::new(PeerId::Public(0), RelayKind::Erlay.create(), config);
+            peer.add_outbound_peer(PeerId::Private(0), addr.clone(), 0);
+            peer.add_outbound_peer(PeerId::Private(1), addr, 1);
+
+            // Only the first peer asks for transactions paying at least the default fee rate
+            peer.fee_filters
+                .insert(PeerId::Private(0), DEFAULT_FEE_RATE);
+            assert!(peer.accept_tx(cheap));
+            assert!(peer.accept_tx(paying));
+            assert_eq!(peer.txs_filtered, 1);
+
+            let filtered = peer.short_ids(PeerId::Private(0)).unwrap();
+            assert_eq!(filtered.len(), 1);
+            assert!(filtered.contains(peer.short_id(PeerId::Private(0), paying.id).unwrap()));
+            assert_eq!(peer.short_ids(PeerId::Private(1)).unwrap().len(), 2);
+
+            assert!(!peer.relays_to(PeerId::Private(0), &cheap));
+            assert!(peer.relays_to(PeerId::Private(0), &paying));
+            assert!(peer.relays_to(PeerId::Private(1), &cheap));
+            let _ = checked_tx.send(());
+
+            System::current().stop();
+        });
+
+        checked_rx.recv().expect("Fee filter checked");
+    }
+
+    #[test]
+    pub fn test_removed_txs_bounded() {
+        let (results_tx, _results_rx) = mpsc::channel();
+        let (checked_tx, checked_rx) = mpsc::channel();
+        let _ = System::run(move || {
+            let config =
+                SimulationBuilder::new().peer_config(Arc::new(TxRegistry::new()), results_tx);
+            let mut peer = Peer::new(PeerId::Public(0), RelayKind::Erlay.create(), config);
+            for i in 0..=MAX_REMOVED_TXS {
+                peer.forget_tx(TxId(i as u32));
+            }
+
+            // The oldest transaction is forgotten first
+            assert_eq!(peer.removed.len(), MAX_REMOVED_TXS);
+            assert!(!peer.removed.contains(&TxId(0)));
+            assert!(peer.removed.contains(&TxId(MAX_REMOVED_TXS as u32)));
+            let _ = checked_tx.send(());
+
+            System::current().stop();
+        });
+
+        checked_rx.recv().expect("Removed transactions checked");
+    }
+
+    #[test]
+    pub fn test_peer_id_roundtrip() {
+        let ids = [
+            0u32,
+            1,
+            (1 << 16) - 1,
+            1 << 16,
+            1_000_000,
+            16_777_216,
+            u32::MAX,
+        ];
+
+        for id in ids.iter() {
+            for peer_id in [PeerId::Public(*id), PeerId::Private(*id)].iter() {
+                let encoded: u64 = (*peer_id).into();
+                assert_eq!(PeerId::from(encoded), *peer_id);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_peer_id_no_collisions() {
+        let mut encoded = std::collections::HashSet::new();
+
+        for id in 0u32..1_000_000 {
+            assert!(encoded.insert(u64::from(PeerId::Public(id))));
+            assert!(encoded.insert(u64::from(PeerId::Private(id))));
+        }
+    }
+
+    #[test]
+    pub fn test_memory_per_tx() {
+        // Connections don't keep transactions of their own
+        let bytes = bytes_per_tx(8);
+        assert_eq!(bytes, bytes_per_tx(1));
+        assert!(bytes < 200, "{} bytes per transaction", bytes);
     }
 }
@@ -0,0 +1,604 @@
+//! Pure-Rust PinSketch: a set sketch made of odd power sums of its elements
+//! in GF(2^bits), decoded as a BCH code.
+//!
+//! Sketches of the same capacity and width are merged with XOR, and the merged sketch
+//! decodes to the symmetric difference of the sets, as long as it fits the capacity.
+
+use crate::recset::RecSetError;
+use crate::sketch::SetSketch;
+
+/// Low terms of irreducible polynomials `x^bits + ...` that define GF(2^bits),
+/// for `bits` from 2 to 64.
+const MODULI: [u64; 63] = [
+    0x3, 0x3, 0x3, 0x5, 0x3, 0x3, 0x1b, 0x3, 0x9, 0x5, 0x9, 0x1b, 0x21, 0x3, 0x2b, 0x9, 0x9, 0x27,
+    0x9, 0x5, 0x3, 0x21, 0x1b, 0x9, 0x1b, 0x27, 0x3, 0x5, 0x3, 0x9, 0x8d, 0x401, 0x81, 0x5, 0x201,
+    0x53, 0x63, 0x11, 0x39, 0x9, 0x81, 0x59, 0x21, 0x1b, 0x3, 0x21, 0x2d, 0x201, 0x1d, 0x4b, 0x9,
+    0x47, 0x201, 0x81, 0x95, 0x11, 0x80001, 0x95, 0x3, 0x27, 0x20000001, 0x3, 0x1b,
+];
+
+/// Binary field GF(2^bits).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Field {
+    bits: u32,
+    modulus: u64,
+}
+
+impl Field {
+    fn new(bits: u32) -> Self {
+        Field {
+            bits,
+            modulus: MODULI[bits as usize - 2],
+        }
+    }
+
+    fn mask(self) -> u64 {
+        if self.bits == 64 {
+            u64::MAX
+        } else {
+            (1 << self.bits) - 1
+        }
+    }
+
+    /// Carry-less product of `a` and `b`, four bits of `b` at a time.
+    fn clmul(a: u64, b: u64) -> u128 {
+        let mut multiples = [0u128; 16];
+        for i in 1..16 {
+            multiples[i] = if i % 2 == 0 {
+                multiples[i / 2] << 1
+            } else {
+                multiples[i - 1] ^ u128::from(a)
+            };
+        }
+
+        let mut product = 0u128;
+        for nibble in (0..16).rev() {
+            product = (product << 4) ^ multiples[((b >> (nibble * 4)) & 0xf) as usize];
+        }
+
+        product
+    }
+
+    /// Carry-less product of `a` and sparse `b`.
+    fn clmul_sparse(a: u64, b: u64) -> u128 {
+        let mut product = 0u128;
+        let mut b = b;
+        let mut shift = 0;
+
+        while b != 0 {
+            let zeros = b.trailing_zeros();
+            shift += zeros;
+            product ^= u128::from(a) << shift;
+            b >>= zeros;
+            b >>= 1;
+            shift += 1;
+        }
+
+        product
+    }
+
+    fn mul(self, a: u64, b: u64) -> u64 {
+        let mut product = Self::clmul(a, b);
+
+        // Fold the high part back with x^bits = modulus until it fits the field
+        loop {
+            let high = (product >> self.bits) as u64;
+            if high == 0 {
+                return product as u64;
+            }
+
+            product = (product & u128::from(self.mask())) ^ Self::clmul_sparse(high, self.modulus);
+        }
+    }
+
+    fn square(self, a: u64) -> u64 {
+        self.mul(a, a)
+    }
+
+    /// Multiplicative inverse of a non-zero element, with extended Euclid's algorithm.
+    fn inv(self, a: u64) -> u64 {
+        let degree = |p: u128| 127 - p.leading_zeros() as i32;
+
+        let (mut u, mut v) = (u128::from(a), u128::from(self.modulus) | (1 << self.bits));
+        let (mut g1, mut g2) = (1u128, 0u128);
+        while u > 1 {
+            let mut shift = degree(u) - degree(v);
+            if shift < 0 {
+                std::mem::swap(&mut u, &mut v);
+                std::mem::swap(&mut g1, &mut g2);
+                shift = -shift;
+            }
+
+            u ^= v << shift;
+            g1 ^= g2 << shift;
+        }
+
+        g1 as u64
+    }
+}
+
+/// Polynomial over GF(2^bits), lowest coefficient first, without trailing zeros.
+type Poly = Vec<u64>;
+
+fn trim(mut p: Poly) -> Poly {
+    while p.last() == Some(&0) {
+        p.pop();
+    }
+
+    p
+}
+
+fn degree(p: &[u64]) -> usize {
+    p.len().saturating_sub(1)
+}
+
+/// Remainder of `a` divided by monic `m`.
+fn poly_rem(field: Field, mut a: Poly, m: &[u64]) -> Poly {
+    let dm = degree(m);
+
+    while a.len() > dm {
+        let lead = *a.last().expect("Non-empty polynomial");
+        let shift = a.len() - 1 - dm;
+        if lead != 0 {
+            for (i, c) in m.iter().enumerate() {
+                a[shift + i] ^= field.mul(lead, *c);
+            }
+        }
+        a.pop();
+    }
+
+    trim(a)
+}
+
+/// Quotient of `a` divided by monic `m`, when `m` divides `a`.
+fn poly_div(field: Field, a: &[u64], m: &[u64]) -> Poly {
+    let dm = degree(m);
+    let mut a = a.to_vec();
+    let mut quotient = vec![0; a.len() - dm];
+
+    while a.len() > dm {
+        let lead = *a.last().expect("Non-empty polynomial");
+        let shift = a.len() - 1 - dm;
+        quotient[shift] = lead;
+        for (i, c) in m.iter().enumerate() {
+            a[shift + i] ^= field.mul(lead, *c);
+        }
+        a.pop();
+    }
+
+    trim(quotient)
+}
+
+fn make_monic(field: Field, mut p: Poly) -> Poly {
+    if let Some(lead) = p.last().cloned() {
+        let inv = field.inv(lead);
+        for c in p.iter_mut() {
+            *c = field.mul(*c, inv);
+        }
+    }
+
+    p
+}
+
+fn poly_gcd(field: Field, a: Poly, b: Poly) -> Poly {
+    let (mut a, mut b) = (trim(a), trim(b));
+
+    while !b.is_empty() {
+        let monic = make_monic(field, b);
+        let rem = poly_rem(field, a, &monic);
+        a = monic;
+        b = rem;
+    }
+
+    make_monic(field, a)
+}
+
+/// Square of `p` modulo monic `m`.
+fn poly_square_rem(field: Field, p: &[u64], m: &[u64]) -> Poly {
+    let mut square = vec![0; p.len() * 2];
+    for (i, c) in p.iter().enumerate() {
+        square[i * 2] = field.square(*c);
+    }
+
+    poly_rem(field, square, m)
+}
+
+/// Traces of `beta * x` for basis elements `beta`, modulo a polynomial.
+struct Traces {
+    /// `x^(2^i)` for `i` in `0..bits`.
+    powers: Vec<Poly>,
+
+    /// Traces of `(1 << k) * x` computed so far.
+    traces: Vec<Poly>,
+}
+
+impl Traces {
+    /// Trace of `(1 << k) * x`, a sum of `(1 << k)^(2^i) * x^(2^i)`.
+    fn get(&mut self, field: Field, k: u32) -> &Poly {
+        while self.traces.len() <= k as usize {
+            let mut beta = 1u64 << self.traces.len();
+            let mut trace = vec![];
+            for power in self.powers.iter() {
+                trace.resize(trace.len().max(power.len()), 0);
+                for (t, c) in trace.iter_mut().zip(power.iter()) {
+                    *t ^= field.mul(beta, *c);
+                }
+                beta = field.square(beta);
+            }
+
+            self.traces.push(trace);
+        }
+
+        &self.traces[k as usize]
+    }
+}
+
+/// Finds roots of monic `p` that has distinct roots, all of them in the field.
+///
+/// `traces` are modulo a multiple of `p`, and basis elements below `1 << first`
+/// are known not to split the roots.
+fn find_roots(
+    field: Field,
+    p: Poly,
+    traces: &mut Traces,
+    first: u32,
+    roots: &mut Vec<u64>,
+) -> Result<(), RecSetError> {
+    match degree(&p) {
+        0 => return Ok(()),
+        1 => {
+            roots.push(p[0]);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Trace of `beta * x` maps roots to 0 or 1, for some basis element `beta`
+    // it splits them into two non-empty groups
+    for k in first..field.bits {
+        let trace = poly_rem(field, traces.get(field, k).clone(), &p);
+        let factor = poly_gcd(field, p.clone(), trace);
+        if degree(&factor) > 0 && degree(&factor) < degree(&p) {
+            let other = poly_div(field, &p, &factor);
+            find_roots(field, factor, traces, k + 1, roots)?;
+            return find_roots(field, other, traces, k + 1, roots);
+        }
+    }
+
+    Err(RecSetError::DecodeFailed)
+}
+
+/// PinSketch of a set of non-zero `bits`-wide elements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinSketch {
+    field: Field,
+
+    /// Sums of odd powers `x`, `x^3`, ..., `x^(2 * capacity - 1)` of all elements.
+    syndromes: Vec<u64>,
+}
+
+impl PinSketch {
+    /// Creates an empty sketch of `capacity` elements of `bits` bits, from 2 to 64.
+    pub fn new(bits: u32, capacity: usize) -> Self {
+        assert!((2..=64).contains(&bits), "Unsupported field size: {}", bits);
+
+        PinSketch {
+            field: Field::new(bits),
+            syndromes: vec![0; capacity],
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.field.bits
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.syndromes.len()
+    }
+
+    /// Connection polynomial of the power sums, found with Berlekamp-Massey.
+    fn locator(&self) -> Poly {
+        let field = self.field;
+
+        // Even power sums are squares of the halves: s(2k) = s(k)^2
+        let mut sums = vec![0u64; self.capacity() * 2 + 1];
+        for k in 1..sums.len() {
+            sums[k] = if k % 2 == 1 {
+                self.syndromes[k / 2]
+            } else {
+                field.square(sums[k / 2])
+            };
+        }
+
+        let mut current: Poly = vec![1];
+        let mut previous: Poly = vec![1];
+        let mut length = 0;
+        let mut shift = 1;
+        let mut previous_discrepancy = 1u64;
+
+        for n in 0..self.capacity() * 2 {
+            let mut discrepancy = sums[n + 1];
+            for i in 1..=length.min(current.len() - 1) {
+                discrepancy ^= field.mul(current[i], sums[n + 1 - i]);
+            }
+
+            if discrepancy == 0 {
+                shift += 1;
+                continue;
+            }
+
+            let scale = field.mul(discrepancy, field.inv(previous_discrepancy));
+            let mut next = current.clone();
+            next.resize(next.len().max(previous.len() + shift), 0);
+            for (i, c) in previous.iter().enumerate() {
+                next[i + shift] ^= field.mul(scale, *c);
+            }
+
+            if 2 * length <= n {
+                previous = current;
+                length = n + 1 - length;
+                previous_discrepancy = discrepancy;
+                shift = 1;
+            } else {
+                shift += 1;
+            }
+
+            current = next;
+        }
+
+        current.resize(length + 1, 0);
+        current
+    }
+}
+
+impl SetSketch for PinSketch {
+    fn add(&mut self, element: u64) {
+        let field = self.field;
+        let x = element & field.mask();
+        let square = field.square(x);
+
+        let mut power = x;
+        for syndrome in self.syndromes.iter_mut() {
+            *syndrome ^= power;
+            power = field.mul(power, square);
+        }
+    }
+
+    fn serialized_size(&self) -> usize {
+        (self.capacity() * self.bits() as usize).div_ceil(8)
+    }
+
+    fn serialize(&self, buf: &mut [u8]) -> Result<(), RecSetError> {
+        if buf.len() < self.serialized_size() {
+            return Err(RecSetError::InvalidSketch);
+        }
+
+        let bits = self.bits() as usize;
+        for b in buf.iter_mut().take(self.serialized_size()) {
+            *b = 0;
+        }
+        for (i, syndrome) in self.syndromes.iter().enumerate() {
+            for bit in 0..bits {
+                if (syndrome >> bit) & 1 == 1 {
+                    let pos = i * bits + bit;
+                    buf[pos / 8] |= 1 << (pos % 8);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn deserialize(&mut self, buf: &[u8]) {
+        let bits = self.bits() as usize;
+        for (i, syndrome) in self.syndromes.iter_mut().enumerate() {
+            *syndrome = 0;
+            for bit in 0..bits {
+                let pos = i * bits + bit;
+                if buf.get(pos / 8).is_some_and(|b| (b >> (pos % 8)) & 1 == 1) {
+                    *syndrome |= 1 << bit;
+                }
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<(), RecSetError> {
+        if self.field != other.field || self.capacity() != other.capacity() {
+            return Err(RecSetError::InvalidSketch);
+        }
+
+        for (a, b) in self.syndromes.iter_mut().zip(other.syndromes.iter()) {
+            *a ^= *b;
+        }
+
+        Ok(())
+    }
+
+    fn decode(&self, elements: &mut [u64]) -> Result<usize, RecSetError> {
+        let locator = self.locator();
+        let num = degree(&locator);
+        if num == 0 {
+            return Ok(0);
+        }
+
+        // Zero is never an element, so the locator has full degree
+        if num > elements.len() || locator[num] == 0 {
+            return Err(RecSetError::DecodeFailed);
+        }
+
+        // Elements are roots of the reversed locator, which must split into distinct
+        // linear factors: it divides x^(2^bits) - x
+        let reversed = make_monic(self.field, locator.into_iter().rev().collect());
+        let mut powers = vec![poly_rem(self.field, vec![0, 1], &reversed)];
+        for i in 0..self.bits() as usize {
+            powers.push(poly_square_rem(self.field, &powers[i], &reversed));
+        }
+        if powers.pop() != Some(powers[0].clone()) {
+            return Err(RecSetError::DecodeFailed);
+        }
+
+        let mut traces = Traces {
+            powers,
+            traces: vec![],
+        };
+        let mut roots = Vec::with_capacity(num);
+        find_roots(self.field, reversed, &mut traces, 0, &mut roots)?;
+        if roots.len() != num {
+            return Err(RecSetError::DecodeFailed);
+        }
+
+        elements[..num].copy_from_slice(&roots);
+        Ok(num)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{poly_rem, poly_square_rem, Field, PinSketch, Traces};
+    use crate::recset::RecSetError;
+    use crate::sketch::SetSketch;
+
+    /// Carry-less product, one bit of `b` at a time.
+    fn naive_clmul(a: u64, b: u64) -> u128 {
+        (0..64)
+            .filter(|i| (b >> i) & 1 == 1)
+            .fold(0, |product, i| product ^ (u128::from(a) << i))
+    }
+
+    /// Product in `field`, reducing after every shift.
+    fn naive_mul(field: Field, a: u64, b: u64) -> u64 {
+        let mut product = 0;
+        for i in (0..field.bits).rev() {
+            let carry = (product >> (field.bits - 1)) & 1 == 1;
+            product = (product << 1) & field.mask();
+            if carry {
+                product ^= field.modulus;
+            }
+            if (b >> i) & 1 == 1 {
+                product ^= a;
+            }
+        }
+
+        product
+    }
+
+    #[test]
+    pub fn test_field_arithmetic() {
+        let values = (1..50u64)
+            .map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ (i << 7))
+            .collect::<Vec<_>>();
+
+        for (a, b) in values.iter().zip(values.iter().rev()) {
+            assert_eq!(Field::clmul(*a, *b), naive_clmul(*a, *b));
+            assert_eq!(Field::clmul_sparse(*a, *b), naive_clmul(*a, *b));
+        }
+
+        for bits in [2u32, 8, 13, 32, 58, 64].iter().cloned() {
+            let field = Field::new(bits);
+            for (a, b) in values.iter().zip(values.iter().rev()) {
+                let (a, b) = (a & field.mask(), b & field.mask());
+                assert_eq!(field.mul(a, b), naive_mul(field, a, b), "{} bits", bits);
+                if a != 0 {
+                    assert_eq!(field.mul(a, field.inv(a)), 1, "{} bits", bits);
+                }
+            }
+
+            // Traces are polynomials whose value at every root is the trace of `beta * root`
+            let roots = values
+                .iter()
+                .map(|v| v & field.mask())
+                .filter(|v| *v != 0)
+                .take(3)
+                .collect::<Vec<_>>();
+            let m = roots.iter().fold(vec![1], |m, root| {
+                let mut product = vec![0; m.len() + 1];
+                for (i, c) in m.iter().enumerate() {
+                    product[i] ^= field.mul(*c, *root);
+                    product[i + 1] ^= c;
+                }
+                product
+            });
+            let mut powers = vec![poly_rem(field, vec![0, 1], &m)];
+            for i in 0..bits as usize - 1 {
+                powers.push(poly_square_rem(field, &powers[i], &m));
+            }
+            let mut traces = Traces {
+                powers,
+                traces: vec![],
+            };
+
+            for k in 0..bits {
+                let trace = traces.get(field, k).clone();
+                for root in roots.iter() {
+                    let value = trace
+                        .iter()
+                        .rev()
+                        .fold(0, |value, c| naive_mul(field, value, *root) ^ c);
+                    let mut expected = 0;
+                    let mut power = naive_mul(field, 1 << k, *root);
+                    for _ in 0..bits {
+                        expected ^= power;
+                        power = naive_mul(field, power, power);
+                    }
+                    assert_eq!(value, expected, "{} bits, k = {}", bits, k);
+                    assert!(expected <= 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_pinsketch_decode() {
+        for bits in [2u32, 8, 12, 32, 58, 64].iter().cloned() {
+            let field = Field::new(bits);
+            let x = 0x1234_5678_9abc_def1 & field.mask();
+            assert_eq!(
+                field.mul(x, field.inv(x.max(1))),
+                if x == 0 { 0 } else { 1 }
+            );
+
+            let capacity = 6;
+            let mut a = PinSketch::new(bits, capacity);
+            let mut b = PinSketch::new(bits, capacity);
+            let mut expected = vec![];
+            for i in 1..=(field.mask().min(20)) {
+                let element = i.wrapping_mul(0x9e37_79b9_7f4a_7c15) & field.mask();
+                if element == 0 || expected.contains(&element) {
+                    continue;
+                }
+
+                if expected.len() < capacity && i % 3 != 0 {
+                    a.add(element);
+                    expected.push(element);
+                } else if i % 3 == 0 {
+                    a.add(element);
+                    b.add(element);
+                }
+            }
+
+            let mut buf = vec![0u8; b.serialized_size()];
+            b.serialize(&mut buf).expect("Serialize");
+            let mut received = PinSketch::new(bits, capacity);
+            received.deserialize(&buf);
+            assert_eq!(received, b);
+
+            a.merge(&received).expect("Merge");
+            let mut elements = vec![0u64; capacity];
+            let num = a.decode(&mut elements).expect("Decode");
+            let mut decoded = elements[..num].to_vec();
+            decoded.sort();
+            expected.sort();
+            assert_eq!(decoded, expected, "{} bits", bits);
+        }
+    }
+
+    #[test]
+    pub fn test_pinsketch_overfull() {
+        let mut sketch = PinSketch::new(32, 4);
+        for element in 1..=6 {
+            sketch.add(element * 1_000_003);
+        }
+
+        let mut elements = vec![0u64; 4];
+        assert_eq!(sketch.decode(&mut elements), Err(RecSetError::DecodeFailed));
+    }
+}
@@ -1,88 +1,214 @@
 //! Defines set that can be reconciled.
 
-use minisketch_rs;
-use minisketch_rs::Minisketch;
 use std::collections::HashSet;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hash;
 
+use crate::short_ids::ShortIdKey;
+use crate::sketch::{SetSketch, Sketch, SketchKind};
+
+/// Errors that can occur during set reconciliation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecSetError {
+    /// Set difference is larger than sketch capacity, so it can't be decoded.
+    DecodeFailed,
+
+    /// Sketch is malformed or has a different capacity.
+    InvalidSketch,
+}
+
+impl Display for RecSetError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            RecSetError::DecodeFailed => write!(f, "sketch difference can't be decoded"),
+            RecSetError::InvalidSketch => write!(f, "invalid sketch"),
+        }
+    }
+}
+
+impl std::error::Error for RecSetError {}
+
+/// Narrowest short IDs a sketch supports, in bits.
+pub const MIN_SHORT_ID_BITS: u32 = 2;
+
+/// Widest short IDs a sketch supports, in bits.
+pub const MAX_SHORT_ID_BITS: u32 = 64;
+
+/// Truncates short ID `id` to its lowest `bits` bits.
+///
+/// Sketches can't hold zero, so short IDs that truncate to zero become one.
+pub fn truncate_short_id(id: u64, bits: u32) -> u64 {
+    let id = if bits >= 64 {
+        id
+    } else {
+        id & ((1u64 << bits) - 1)
+    };
+
+    id.max(1)
+}
+
+/// Parses width of short IDs in bits, from `MIN_SHORT_ID_BITS` to `MAX_SHORT_ID_BITS`.
+pub fn parse_short_id_bits(s: &str) -> Result<u32, String> {
+    s.parse::<u32>()
+        .ok()
+        .filter(|bits| (MIN_SHORT_ID_BITS..=MAX_SHORT_ID_BITS).contains(bits))
+        .ok_or_else(|| {
+            format!(
+                "short ID width must be {} to {} bits, got: {}",
+                MIN_SHORT_ID_BITS, MAX_SHORT_ID_BITS, s
+            )
+        })
+}
+
 /// Types that can produce short ID (short hash) can implement this trait.
 pub trait ShortId<I> {
-    fn short_id(&self) -> I;
+    /// Short ID under the SipHash `key` of a connection.
+    fn short_id(&self, key: ShortIdKey) -> I;
+}
+
+/// Difference between a set and another one it was reconciled with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetDifference<I> {
+    /// Elements this set has and the other one lacks.
+    pub local: Vec<I>,
+
+    /// Elements the other set has and this one lacks.
+    pub remote: Vec<I>,
 }
 
 /// A set that supports reconciliation by using short IDs (`I`) of its elements (`V`)
 #[derive(Debug)]
 pub struct RecSet<I: Hash + Eq + Copy + From<u64> + Into<u64> + Debug> {
+    kind: SketchKind,
     capacity: usize,
+    bits: u32,
     seed: Option<u64>,
-    sketch: Minisketch,
+    sketch: Sketch,
     set: HashSet<I>,
 }
 
 impl<I: Hash + Eq + Copy + From<u64> + Into<u64> + Debug> RecSet<I> {
-    /// Creates new set with given `capacity`.
+    /// Creates new set with given `capacity`, of short IDs as wide as `I`.
     pub fn new(capacity: usize) -> Self {
-        let _bits = std::mem::size_of::<I>() * 8;
-        let sketch = Self::create_minisketch(capacity, None);
+        Self::with_bits(capacity, Self::default_bits())
+    }
+
+    /// Creates new set with given `capacity`, of short IDs truncated to `bits` bits.
+    pub fn with_bits(capacity: usize, bits: u32) -> Self {
+        Self::with_sketch(SketchKind::default(), capacity, bits)
+    }
+
+    /// Creates new set with given `capacity`, of short IDs truncated to `bits` bits,
+    /// that reconciles with sketches of given `kind`.
+    ///
+    /// # Panics
+    ///
+    /// If `bits` is out of `MIN_SHORT_ID_BITS..=MAX_SHORT_ID_BITS`.
+    pub fn with_sketch(kind: SketchKind, capacity: usize, bits: u32) -> Self {
+        assert!(
+            (MIN_SHORT_ID_BITS..=MAX_SHORT_ID_BITS).contains(&bits),
+            "Unsupported short ID width: {}",
+            bits
+        );
 
         RecSet {
+            kind,
             seed: None,
             capacity,
-            sketch,
+            bits,
+            sketch: kind.create(capacity, bits, None),
             set: HashSet::with_capacity(capacity),
         }
     }
 
-    /// Creates new set with given `capacity` and `seed` for underlying Minisketch math.
+    /// Creates new set with given `capacity` and `seed` for underlying sketch math.
     #[allow(dead_code)]
     pub fn with_seed(capacity: usize, seed: u64) -> Self {
-        let sketch = Self::create_minisketch(capacity, Some(seed));
+        let kind = SketchKind::default();
+        let bits = Self::default_bits();
 
         RecSet {
-            seed: None,
+            kind,
+            seed: Some(seed),
             capacity,
-            sketch,
+            bits,
+            sketch: kind.create(capacity, bits, Some(seed)),
             set: HashSet::with_capacity(capacity),
         }
     }
 
-    /// Adds element to the sketch.
+    fn default_bits() -> u32 {
+        (std::mem::size_of::<I>() * 8) as u32
+    }
+
+    /// Truncates `id` to the width of short IDs of this set.
+    pub fn truncate(&self, id: I) -> I {
+        I::from(truncate_short_id(id.into(), self.bits))
+    }
+
+    /// Adds element to the sketch, truncated to the width of short IDs.
     /// Element will be added only if it's not already in the set.
     pub fn insert(&mut self, id: I) {
+        let id = self.truncate(id);
+
         if !self.set.contains(&id) {
             self.set.insert(id);
             self.sketch.add(id.into());
         }
     }
 
-    fn create_minisketch(capacity: usize, seed: Option<u64>) -> Minisketch {
-        let bits = std::mem::size_of::<I>() * 8;
-        let mut minisketch = Minisketch::try_new(bits as u32, 0, capacity).unwrap();
+    /// Removes element from the set and the sketch, truncated to the width of short IDs.
+    ///
+    /// Returns `false` if the element wasn't in the set.
+    pub fn remove(&mut self, id: I) -> bool {
+        let id = self.truncate(id);
 
-        if let Some(seed) = seed {
-            minisketch.set_seed(seed);
+        // Adding an element that is already in the sketch cancels it out
+        let removed = self.set.remove(&id);
+        if removed {
+            self.sketch.add(id.into());
         }
 
-        minisketch
+        removed
+    }
+
+    /// Removes all elements, leaving the sketch empty.
+    pub fn clear(&mut self) {
+        self.set.clear();
+        self.sketch = self.kind.create(self.capacity, self.bits, self.seed);
+    }
+
+    /// Inserts all elements of `ids`.
+    pub fn extend_from<T: IntoIterator<Item = I>>(&mut self, ids: T) {
+        for id in ids {
+            self.insert(id);
+        }
     }
 
     pub fn reconcile(
+        kind: SketchKind,
         sketch_a: &[u8],
         sketch_b: &[u8],
         capacity: usize,
+        bits: u32,
         seed: Option<u64>,
-    ) -> Result<Vec<I>, ()> {
-        let mut a = Self::create_minisketch(capacity, seed);
-        a.deserialize(sketch_a);
+    ) -> Result<Vec<I>, RecSetError> {
+        let mut a = kind.create(capacity, bits, seed);
+        let mut b = kind.create(capacity, bits, seed);
+
+        // Sketches read exactly `serialized_size` bytes, whatever the length of the input
+        let size = a.serialized_size();
+        if sketch_a.len() != size || sketch_b.len() != size {
+            return Err(RecSetError::InvalidSketch);
+        }
 
-        let mut b = Self::create_minisketch(capacity, seed);
+        a.deserialize(sketch_a);
         b.deserialize(sketch_b);
 
-        a.merge(&b).expect("Minisketch merge");
+        a.merge(&b)?;
 
         let mut diffs = vec![0u64; capacity];
-        let num_diffs = a.decode(&mut diffs).map_err(|_| ())?;
+        let num_diffs = a.decode(&mut diffs)?;
 
         let diff_ids = diffs
             .iter()
@@ -92,32 +218,40 @@ impl<I: Hash + Eq + Copy + From<u64> + Into<u64> + Debug> RecSet<I> {
         Ok(diff_ids.into_iter().take(num_diffs).collect())
     }
 
-    /// Produces list of IDs that are missing in the set given as its `sketch`.
-    pub fn reconcile_with(&mut self, sketch_b: &[u8]) -> Result<Vec<I>, ()> {
-        Self::reconcile(&self.sketch(), sketch_b, self.capacity, self.seed)
+    /// Finds the difference with the set given as its `sketch`, split by the side
+    /// that has the elements.
+    pub fn reconcile_with(&mut self, sketch_b: &[u8]) -> Result<SetDifference<I>, RecSetError> {
+        let diff = Self::reconcile(
+            self.kind,
+            &self.sketch(),
+            sketch_b,
+            self.capacity,
+            self.bits,
+            self.seed,
+        )?;
+
+        let (local, remote) = diff.into_iter().partition(|id| self.set.contains(id));
+        Ok(SetDifference { local, remote })
     }
 
     #[allow(dead_code)]
     pub fn bisect_with(
+        kind: SketchKind,
         a_whole: &[u8],
         a_half: &[u8],
         b_whole: &[u8],
         b_half: &[u8],
         capacity: usize,
         seed: Option<u64>,
-    ) -> Result<Vec<I>, ()> {
+    ) -> Result<Vec<I>, RecSetError> {
+        let bits = Self::default_bits();
+
         // Extracts remainder sketch from a difference of two sketches
-        pub fn sub_sketches(s1: &[u8], s2: &[u8], d: usize, seed: Option<u64>) -> Vec<u8> {
-            let mut a = minisketch_rs::Minisketch::try_new(64, 0, d).unwrap();
-            if let Some(seed) = seed {
-                a.set_seed(seed);
-            }
+        let sub_sketches = |s1: &[u8], s2: &[u8], d: usize, seed: Option<u64>| {
+            let mut a = kind.create(d, bits, seed);
             a.deserialize(s1);
 
-            let mut b = minisketch_rs::Minisketch::try_new(64, 0, d).unwrap();
-            if let Some(seed) = seed {
-                b.set_seed(seed);
-            }
+            let mut b = kind.create(d, bits, seed);
             b.deserialize(s2);
 
             a.merge(&b).expect("Sketch sub merge");
@@ -126,7 +260,7 @@ impl<I: Hash + Eq + Copy + From<u64> + Into<u64> + Debug> RecSet<I> {
             a.serialize(&mut sketch).expect("Serialize sketch sub");
 
             sketch
-        }
+        };
 
         // Try bisection:
         //
@@ -137,19 +271,14 @@ impl<I: Hash + Eq + Copy + From<u64> + Into<u64> + Debug> RecSet<I> {
         //
         // b_half is known to Alice since Bob sent his b_half sketch to her before bisect
 
-        let a_minus_a_2 = sub_sketches(&a_whole, &a_half, capacity, seed);
-        let b_minus_b_2 = sub_sketches(&b_whole, &b_half, capacity, seed);
+        let a_minus_a_2 = sub_sketches(a_whole, a_half, capacity, seed);
+        let b_minus_b_2 = sub_sketches(b_whole, b_half, capacity, seed);
 
-        let res_1 = RecSet::<I>::reconcile(&a_half, &b_half, capacity, seed);
-        let res_2 = RecSet::<I>::reconcile(&a_minus_a_2, &b_minus_b_2, capacity, seed);
+        let res_1 = RecSet::<I>::reconcile(kind, a_half, b_half, capacity, bits, seed);
+        let res_2 = RecSet::<I>::reconcile(kind, &a_minus_a_2, &b_minus_b_2, capacity, bits, seed);
 
         res_1.and_then(|diffs1| {
-            res_2.and_then(|diffs2| {
-                Ok(diffs1
-                    .into_iter()
-                    .chain(diffs2.into_iter())
-                    .collect::<Vec<_>>())
-            })
+            res_2.map(|diffs2| diffs1.into_iter().chain(diffs2).collect::<Vec<_>>())
         })
     }
 
@@ -157,13 +286,26 @@ impl<I: Hash + Eq + Copy + From<u64> + Into<u64> + Debug> RecSet<I> {
     /// It is used in set reconciliation to find out what elements are missing in this set.
     pub fn sketch(&self) -> Vec<u8> {
         let mut buf = vec![0u8; self.sketch.serialized_size()];
-        self.sketch
-            .serialize(&mut buf)
-            .expect("Minisketch serialize");
+        self.sketch.serialize(&mut buf).expect("Sketch serialize");
 
         buf
     }
 
+    /// Maximum number of differences a sketch of this set can decode.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Width of short IDs in bits.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Kind of sketches this set reconciles with.
+    pub fn kind(&self) -> SketchKind {
+        self.kind
+    }
+
     pub fn contains(&self, id: &I) -> bool {
         self.set.contains(id)
     }
@@ -172,11 +314,19 @@ impl<I: Hash + Eq + Copy + From<u64> + Into<u64> + Debug> RecSet<I> {
     pub fn len(&self) -> usize {
         self.set.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{RecSet, ShortId};
+    use super::{
+        parse_short_id_bits, truncate_short_id, RecSet, RecSetError, SetDifference, ShortId,
+    };
+    use crate::short_ids::ShortIdKey;
+    use crate::sketch::SketchKind;
     use siphasher::sip::SipHasher;
     use std::hash::Hasher;
 
@@ -184,8 +334,8 @@ mod test {
     pub struct Tx(pub [u8; 32]);
 
     impl ShortId<u64> for Tx {
-        fn short_id(&self) -> u64 {
-            let mut hasher = SipHasher::new_with_keys(0xDEu64, 0xADu64);
+        fn short_id(&self, key: ShortIdKey) -> u64 {
+            let mut hasher = SipHasher::new_with_keys(key.0, key.1);
             hasher.write(&self.0);
             hasher.finish()
         }
@@ -199,26 +349,122 @@ mod test {
 
         let mut rec_set_alice = RecSet::<u64>::with_seed(16, 42u64);
         for tx in txs_alice.iter() {
-            rec_set_alice.insert(tx.clone().short_id());
+            rec_set_alice.insert(tx.clone().short_id(ShortIdKey::UNSALTED));
         }
 
         let mut rec_set_bob = RecSet::<u64>::with_seed(16, 42u64);
         for tx in txs_bob {
-            rec_set_bob.insert(tx.short_id());
+            rec_set_bob.insert(tx.short_id(ShortIdKey::UNSALTED));
         }
 
         let bob_sketch = rec_set_bob.sketch();
-        let missing = rec_set_alice
+        let diff = rec_set_alice
             .reconcile_with(&bob_sketch)
             .expect("Reconcile with Alice");
 
-        assert_eq!(missing.len(), 2);
-
-        for id in missing {
+        assert_eq!(diff.local.len(), 2);
+        for id in diff.local {
             assert!(rec_set_alice.contains(&id));
         }
     }
 
+    #[test]
+    pub fn test_short_id_bits() {
+        let a = (1..=20u64).map(|i| i * 0x1_0000_0001).collect::<Vec<_>>();
+
+        for bits in [2u32, 12, 32, 64].iter().cloned() {
+            let mut alice = RecSet::<u64>::with_bits(8, bits);
+            let mut bob = RecSet::<u64>::with_bits(8, bits);
+            for id in a.iter() {
+                alice.insert(*id);
+            }
+            for id in a.iter().take(17) {
+                bob.insert(*id);
+            }
+
+            // Capacity of 8 elements, `bits` bits each
+            assert_eq!(alice.sketch().len(), bits as usize);
+
+            // Truncated IDs collide, so there are no more differences than distinct ones
+            let diff = alice.reconcile_with(&bob.sketch()).expect("Reconcile");
+            assert!(diff.remote.is_empty());
+            let mut missing = diff.local;
+            missing.sort();
+            let mut expected = a[17..]
+                .iter()
+                .map(|id| truncate_short_id(*id, bits))
+                .filter(|id| !bob.contains(id))
+                .collect::<Vec<_>>();
+            expected.sort();
+            expected.dedup();
+            assert_eq!(missing, expected, "{} bits", bits);
+        }
+
+        assert_eq!(truncate_short_id(0x1_0000_0000, 32), 1);
+        assert_eq!(parse_short_id_bits("32"), Ok(32));
+        assert!(parse_short_id_bits("1").is_err());
+        assert!(parse_short_id_bits("65").is_err());
+    }
+
+    #[test]
+    pub fn test_remove_and_clear() {
+        for kind in SketchKind::all() {
+            let new_set = || RecSet::<u64>::with_sketch(kind, 16, 32);
+            let empty = new_set().sketch();
+
+            let mut set = new_set();
+            set.extend_from(1..=10);
+            assert!(set.remove(2));
+            assert!(set.remove(4));
+            assert!(!set.remove(4));
+            assert!(!set.remove(42));
+            set.insert(4);
+            // Truncated to the same short ID as 10
+            assert!(set.remove(0x1_0000_000a));
+
+            let mut expected = new_set();
+            expected.extend_from(vec![9, 7, 5, 4, 3, 1, 6, 8]);
+            assert_eq!(set.len(), expected.len(), "{}", kind);
+            assert_eq!(set.sketch(), expected.sketch(), "{}", kind);
+
+            set.clear();
+            assert!(set.is_empty());
+            assert_eq!(set.sketch(), empty, "{}", kind);
+
+            set.extend_from(vec![5, 6]);
+            expected.extend_from(vec![10, 11]);
+            assert_eq!(
+                set.reconcile_with(&expected.sketch()).map(|mut diff| {
+                    diff.remote.sort();
+                    diff
+                }),
+                Ok(SetDifference {
+                    local: vec![],
+                    remote: vec![1, 3, 4, 7, 8, 9, 10, 11]
+                }),
+                "{}",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_sketch() {
+        let mut rec_set = RecSet::<u64>::new(8);
+        rec_set.insert(42);
+
+        let wrong_capacity = RecSet::<u64>::new(16).sketch();
+        let mut truncated = rec_set.sketch();
+        truncated.pop();
+
+        for sketch in [vec![], truncated, wrong_capacity].iter() {
+            assert_eq!(
+                rec_set.reconcile_with(sketch),
+                Err(RecSetError::InvalidSketch)
+            );
+        }
+    }
+
     #[test]
     pub fn test_bisect_reconciliation() {
         let d = 16; // You can change it to 24 to not perform bisect and compare results
@@ -250,7 +496,7 @@ mod test {
 
             let mut set = RecSet::<u64>::new(capacity);
             for tx in txs {
-                set.insert(tx.short_id());
+                set.insert(tx.short_id(ShortIdKey::UNSALTED));
             }
 
             set
@@ -266,8 +512,9 @@ mod test {
         let b_whole = bob_set_full.sketch();
         let b_half = set_from_range(b_half, d).sketch();
 
-        let first_try = RecSet::<u64>::reconcile(&a_whole, &b_whole, d, None);
-        if let Err(()) = first_try {
+        let first_try =
+            RecSet::<u64>::reconcile(SketchKind::default(), &a_whole, &b_whole, d, 64, None);
+        if let Err(_) = first_try {
             println!("Set overfull, trying bisect...");
 
             // Try bisection:
@@ -279,7 +526,15 @@ mod test {
             //
             // b_half is known to Alice since Bob sent his b_half sketch to her before bisect
 
-            let res = RecSet::<u64>::bisect_with(&a_whole, &a_half, &b_whole, &b_half, d, None);
+            let res = RecSet::<u64>::bisect_with(
+                SketchKind::default(),
+                &a_whole,
+                &a_half,
+                &b_whole,
+                &b_half,
+                d,
+                None,
+            );
             match res {
                 Ok(diffs) => println!("Success: {} diffs {:?}", diffs.len(), diffs),
                 Err(_) => println!("Bisection failed"),
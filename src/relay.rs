@@ -0,0 +1,267 @@
+//! Transaction relay strategies that drive how a `Peer` propagates transactions.
+
+use actix::prelude::*;
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::messages::{PeerTx, ReconcileRequest, ReconcileResult, Tx};
+use crate::peer::{Peer, PeerId};
+use crate::trace::Mechanism;
+use crate::RECONCIL_TIMEOUT_SEC;
+
+/// Relay protocol messages that are handled by a strategy rather than by the `Peer` itself.
+pub enum ProtocolMessage {
+    ReconcileRequest(ReconcileRequest),
+    ReconcileResult(ReconcileResult),
+}
+
+/// Relay logic of a peer.
+///
+/// Every hook receives the peer it belongs to together with the peer's actor context,
+/// so strategies can send messages and schedule timers on behalf of the peer.
+pub trait RelayStrategy {
+    /// Name the strategy is selected by.
+    fn name(&self) -> &'static str;
+
+    /// Peer actor has started.
+    fn on_started(&mut self, _peer: &mut Peer, _ctx: &mut Context<Peer>) {}
+
+    /// Peer received a transaction it didn't know about before.
+    fn on_tx_received(&mut self, peer: &mut Peer, ctx: &mut Context<Peer>, from: PeerId, tx: Tx);
+
+    /// Timer scheduled with `Peer::schedule_timer` has fired.
+    fn on_timer(&mut self, _peer: &mut Peer, _ctx: &mut Context<Peer>) {}
+
+    /// Another peer has connected to this one.
+    fn on_peer_connected(&mut self, _peer: &mut Peer, _ctx: &mut Context<Peer>, _id: PeerId) {}
+
+    /// Peer received a relay protocol message.
+    fn on_protocol_message(
+        &mut self,
+        _peer: &mut Peer,
+        _ctx: &mut Context<Peer>,
+        _msg: ProtocolMessage,
+    ) {
+    }
+}
+
+/// Known relay strategies that can be selected by name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RelayKind {
+    Flooding,
+    Erlay,
+}
+
+impl RelayKind {
+    /// Creates a new instance of the strategy for a single peer.
+    pub fn create(self) -> Box<dyn RelayStrategy> {
+        match self {
+            RelayKind::Flooding => Box::new(Flooding),
+            RelayKind::Erlay => Box::new(Erlay::default()),
+        }
+    }
+}
+
+impl Display for RelayKind {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            RelayKind::Flooding => write!(f, "flooding"),
+            RelayKind::Erlay => write!(f, "erlay"),
+        }
+    }
+}
+
+impl FromStr for RelayKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flooding" => Ok(RelayKind::Flooding),
+            "erlay" => Ok(RelayKind::Erlay),
+            _ => Err(format!("unknown relay strategy: {}", s)),
+        }
+    }
+}
+
+/// Floods every new transaction to all connected peers except the one it came from.
+pub struct Flooding;
+
+impl RelayStrategy for Flooding {
+    fn name(&self) -> &'static str {
+        "flooding"
+    }
+
+    fn on_tx_received(&mut self, peer: &mut Peer, _ctx: &mut Context<Peer>, from: PeerId, tx: Tx) {
+        let peers = peer
+            .outbound
+            .iter()
+            .chain(peer.inbound.iter())
+            .filter(|(id, _)| **id != from && peer.relays_to(**id, &tx))
+            .map(|(id, addr)| (*id, addr.clone()))
+            .collect::<Vec<_>>();
+
+        for (id, addr) in peers {
+            let new_msg = PeerTx {
+                from: peer.id,
+                data: tx,
+            };
+
+            peer.send(id, &addr, new_msg);
+        }
+    }
+
+    fn on_peer_connected(&mut self, peer: &mut Peer, ctx: &mut Context<Peer>, id: PeerId) {
+        // Announce the whole mempool to a new connection, so a peer
+        // that was offline can catch up
+        if peer.mempool.is_empty() {
+            return;
+        }
+
+        if let Some(addr) = peer.connection(id) {
+            peer.announce_mempool(ctx, id, &addr, Mechanism::Request);
+        }
+    }
+}
+
+/// Erlay: low-fanout flooding from public peers combined with set reconciliation.
+pub struct Erlay {
+    /// Number of outbound peers public peers flood new transactions to.
+    pub fanout: usize,
+
+    /// Whether the first reconciliation round has started.
+    reconciling: bool,
+}
+
+impl Default for Erlay {
+    fn default() -> Self {
+        Erlay {
+            fanout: 8,
+            reconciling: false,
+        }
+    }
+}
+
+impl RelayStrategy for Erlay {
+    fn name(&self) -> &'static str {
+        "erlay"
+    }
+
+    fn on_started(&mut self, peer: &mut Peer, ctx: &mut Context<Peer>) {
+        peer.schedule_timer(ctx, Duration::from_secs(RECONCIL_TIMEOUT_SEC));
+    }
+
+    fn on_tx_received(&mut self, peer: &mut Peer, _ctx: &mut Context<Peer>, _from: PeerId, tx: Tx) {
+        // Perform low-fanout flooding if it's a public node
+        if peer.is_public() {
+            let peers = peer
+                .shuffled_outbound()
+                .into_iter()
+                .filter(|(id, _)| peer.relays_to(*id, &tx))
+                .take(self.fanout)
+                .collect::<Vec<_>>();
+
+            for (id, addr) in peers {
+                let new_msg = PeerTx {
+                    from: peer.id,
+                    data: tx,
+                };
+
+                peer.send(id, &addr, new_msg);
+            }
+        }
+    }
+
+    fn on_timer(&mut self, peer: &mut Peer, ctx: &mut Context<Peer>) {
+        self.reconciling = true;
+
+        // Send reconciliation requests to shuffled outbound peers
+        for (id, addr) in peer.shuffled_outbound() {
+            peer.request_reconciliation(ctx, id, &addr);
+        }
+
+        if let Some(interval) = peer.config().reconciliation_interval {
+            peer.schedule_timer(ctx, interval);
+        }
+    }
+
+    fn on_peer_connected(&mut self, peer: &mut Peer, ctx: &mut Context<Peer>, id: PeerId) {
+        // Reconcile with a new outbound connection right away, so a peer
+        // that was offline can catch up
+        if !self.reconciling {
+            return;
+        }
+
+        if let Some(addr) = peer.outbound.get(&id).cloned() {
+            peer.request_reconciliation(ctx, id, &addr);
+        }
+    }
+
+    fn on_protocol_message(
+        &mut self,
+        peer: &mut Peer,
+        ctx: &mut Context<Peer>,
+        msg: ProtocolMessage,
+    ) {
+        match msg {
+            ProtocolMessage::ReconcileRequest(msg) => {
+                let (success, missing) = match peer.reconcile_with(msg.from_id, &msg.sketch) {
+                    // Send what the requester lacks, and ask for what this peer lacks
+                    Some(Ok(diff)) => {
+                        peer.push_txs(msg.from_id, &msg.from_addr, diff.local);
+                        (true, diff.remote)
+                    }
+                    // Fall back to announcing the whole mempool, and let the requester
+                    // know it should do the same
+                    Some(Err(_)) => {
+                        peer.announce_mempool(
+                            ctx,
+                            msg.from_id,
+                            &msg.from_addr,
+                            Mechanism::Fallback,
+                        );
+                        (false, vec![])
+                    }
+                    None => return,
+                };
+
+                let rec_res = ReconcileResult {
+                    from_addr: ctx.address(),
+                    from_id: peer.id,
+                    success,
+                    missing,
+                    short_id_bits: peer.config().short_id_bits,
+                };
+
+                peer.send(msg.from_id, &msg.from_addr, rec_res);
+            }
+            ProtocolMessage::ReconcileResult(msg) => {
+                if msg.success {
+                    peer.push_txs(msg.from_id, &msg.from_addr, msg.missing);
+                } else {
+                    peer.announce_mempool(ctx, msg.from_id, &msg.from_addr, Mechanism::Fallback);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RelayKind;
+
+    #[test]
+    pub fn test_relay_kind_names() {
+        for kind in [RelayKind::Flooding, RelayKind::Erlay].iter() {
+            let parsed = kind
+                .to_string()
+                .parse::<RelayKind>()
+                .expect("Parse relay kind");
+            assert_eq!(parsed, *kind);
+            assert_eq!(kind.create().name(), kind.to_string());
+        }
+
+        assert!("gossip".parse::<RelayKind>().is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Short IDs of transactions, salted per connection.
+//!
+//! Peers announce and reconcile transactions by 64-bit short IDs. If everyone used
+//! the same SipHash key, an attacker could grind transactions whose short IDs collide
+//! with others' everywhere. Salting short IDs per connection makes such collisions local
+//! to a single connection and impossible to target.
+
+use siphasher::sip::SipHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+use crate::recset::{RecSet, RecSetError, SetDifference};
+use crate::sketch::SketchKind;
+use crate::tx_registry::TxId;
+
+/// SipHash key short IDs of a connection are computed with.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct ShortIdKey(pub u64, pub u64);
+
+impl ShortIdKey {
+    /// Key every connection uses when short IDs aren't salted.
+    pub const UNSALTED: ShortIdKey = ShortIdKey(0xDE, 0xAD);
+
+    /// Key of a connection between peers with salts `a` and `b`, in any order.
+    pub fn from_salts(a: u64, b: u64) -> Self {
+        let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+        let derive = |tag: &[u8]| {
+            let mut hasher = SipHasher::new_with_keys(lo, hi);
+            hasher.write(tag);
+            hasher.finish()
+        };
+
+        ShortIdKey(derive(b"short-id-k0"), derive(b"short-id-k1"))
+    }
+}
+
+/// Transactions under the short IDs of a single connection, along with the sketch
+/// of their reconciliation set.
+///
+/// Peers derive it from their mempool whenever they build a sketch or an announcement,
+/// and drop it afterwards, so they keep a single set of transactions however many
+/// connections they have.
+#[derive(Debug)]
+pub struct ShortIds {
+    key: ShortIdKey,
+    set: RecSet<u64>,
+    ids: HashMap<u64, TxId>,
+
+    /// Transactions that couldn't be added since their short ID collided.
+    collisions: u64,
+}
+
+impl ShortIds {
+    /// Creates an empty set of short IDs truncated to `bits` bits, reconciled
+    /// with sketches of given `kind`.
+    pub fn new(key: ShortIdKey, kind: SketchKind, capacity: usize, bits: u32) -> Self {
+        ShortIds {
+            key,
+            set: RecSet::with_sketch(kind, capacity, bits),
+            ids: HashMap::new(),
+            collisions: 0,
+        }
+    }
+
+    pub fn key(&self) -> ShortIdKey {
+        self.key
+    }
+
+    /// Adds transaction `tx` under `short_id`, truncated to the width of short IDs.
+    ///
+    /// Returns `false` if a different transaction already has the same short ID.
+    /// The transaction added first keeps it, the other one can't be announced
+    /// or reconciled over this connection.
+    pub fn insert(&mut self, short_id: u64, tx: TxId) -> bool {
+        let short_id = self.truncate(short_id);
+
+        match self.ids.get(&short_id) {
+            Some(known) if *known == tx => true,
+            Some(_) => {
+                self.collisions += 1;
+                false
+            }
+            None => {
+                self.ids.insert(short_id, tx);
+                self.set.insert(short_id);
+                true
+            }
+        }
+    }
+
+    /// Transaction known under `short_id`.
+    pub fn get(&self, short_id: u64) -> Option<TxId> {
+        self.ids.get(&short_id).cloned()
+    }
+
+    pub fn contains(&self, short_id: u64) -> bool {
+        self.set.contains(&short_id)
+    }
+
+    /// Short IDs of all transactions along with the transactions.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, TxId)> + '_ {
+        self.ids.iter().map(|(short_id, tx)| (*short_id, *tx))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Number of transactions that couldn't be added since their short ID collided.
+    pub fn collisions(&self) -> u64 {
+        self.collisions
+    }
+
+    /// Truncates `short_id` to the width of short IDs of this connection.
+    pub fn truncate(&self, short_id: u64) -> u64 {
+        self.set.truncate(short_id)
+    }
+
+    /// Sketch of the reconciliation set of all short IDs.
+    pub fn sketch(&self) -> Vec<u8> {
+        self.set.sketch()
+    }
+
+    /// Finds the difference with the set given as its `sketch`, split by the side
+    /// that has the short IDs.
+    pub fn reconcile_with(&mut self, sketch: &[u8]) -> Result<SetDifference<u64>, RecSetError> {
+        self.set.reconcile_with(sketch)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ShortIdKey, ShortIds};
+    use crate::sketch::SketchKind;
+    use crate::tx_registry::TxId;
+
+    #[test]
+    pub fn test_short_id_collisions() {
+        let key = ShortIdKey::from_salts(7, 42);
+        assert_eq!(key, ShortIdKey::from_salts(42, 7));
+        assert_ne!(key, ShortIdKey::from_salts(7, 43));
+
+        let mut ids = ShortIds::new(key, SketchKind::default(), 8, 64);
+        assert!(ids.insert(1, TxId(0)));
+        assert!(ids.insert(1, TxId(0)));
+        assert!(!ids.insert(1, TxId(1)));
+
+        assert_eq!(ids.get(1), Some(TxId(0)));
+        assert_eq!(ids.len(), 1);
+        assert_eq!(ids.collisions(), 1);
+
+        // Short IDs that only differ in higher bits collide when truncated
+        let mut ids = ShortIds::new(key, SketchKind::default(), 8, 32);
+        assert!(ids.insert(0x1_0000_0002, TxId(0)));
+        assert!(!ids.insert(0x2_0000_0002, TxId(1)));
+        assert_eq!(ids.get(2), Some(TxId(0)));
+
+        // Colliding transactions are left out of the sketch
+        let mut expected = ShortIds::new(key, SketchKind::default(), 8, 32);
+        assert!(expected.insert(2, TxId(0)));
+        assert_eq!(ids.sketch(), expected.sketch());
+    }
+}
@@ -0,0 +1,1321 @@
+//! Builds a network of peers, runs it and collects the results.
+
+use actix::prelude::*;
+
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_xorshift::XorShiftRng;
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use crate::adversary::Role;
+use crate::block::MiningConfig;
+use crate::churn::ChurnConfig;
+use crate::clock::Clock;
+use crate::conflicts::ConflictStats;
+use crate::distribution::ValueDistribution;
+use crate::eclipse::{EclipseConfig, EclipseStats};
+use crate::faults::{FaultSchedule, ScheduledFault};
+use crate::link::{LinkModel, NetworkModel};
+use crate::mempool::MempoolConfig;
+use crate::messages::Connect;
+use crate::orphans::DEFAULT_MAX_ORPHANS;
+use crate::peer::{Peer, PeerConfig, PeerId};
+use crate::relay::RelayKind;
+use crate::sketch::{SetSketch, SketchKind};
+use crate::spy::{SpyEstimate, SpyLog};
+use crate::trace::{Mechanism, TraceEvent, TxTrace};
+use crate::traffic_counter::{TrafficCounter, TrafficData};
+use crate::tx_registry::{TxId, TxRegistry};
+
+/// Default time to wait for a response to a request, in simulated seconds.
+pub const DEFAULT_REQUEST_TIMEOUT_SEC: u64 = 2;
+
+/// Default number of times an unanswered request is sent again.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Default width of short IDs in bits.
+pub const DEFAULT_SHORT_ID_BITS: u32 = 64;
+
+/// Reasons a simulation doesn't produce results.
+#[derive(Debug)]
+pub enum SimulationError {
+    /// The actix system couldn't run.
+    System(io::Error),
+
+    /// The system stopped before the traffic counter reported results.
+    NoResults,
+}
+
+impl Display for SimulationError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            SimulationError::System(err) => write!(f, "actix system failed: {}", err),
+            SimulationError::NoResults => write!(f, "traffic counter didn't report results"),
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+/// Describes a simulation: its network and relay parameters.
+#[derive(Debug, Clone)]
+pub struct SimulationBuilder {
+    relay: RelayKind,
+    num_private_nodes: u32,
+    num_public_nodes: u32,
+    num_spies: u32,
+    seed: Option<u64>,
+    time_scale: f64,
+    duration: Option<u64>,
+    churn: Option<ChurnConfig>,
+    tx_interval: Option<ValueDistribution>,
+    reconciliation_interval: Option<Duration>,
+    reconciliation_capacity: Option<usize>,
+    faults: FaultSchedule,
+    network: NetworkModel,
+    request_timeout: Duration,
+    max_retries: u32,
+    salted_short_ids: bool,
+    short_id_bits: u32,
+    sketch: SketchKind,
+    mempool: MempoolConfig,
+    mining: Option<MiningConfig>,
+    compact_blocks: bool,
+    fee_rate: Option<ValueDistribution>,
+    min_fee_rate: Option<ValueDistribution>,
+    child_share: Option<f64>,
+    max_orphans: usize,
+    replace_share: Option<f64>,
+    double_spend_share: Option<f64>,
+    trace_sample: Option<f64>,
+    roles: HashMap<PeerId, Role>,
+    role_fractions: Vec<(Role, f64)>,
+    eclipse: Option<EclipseConfig>,
+}
+
+/// Results of a finished simulation.
+#[derive(Debug, Clone, Default)]
+pub struct SimulationResults {
+    /// Traffic used by each peer.
+    pub traffic: HashMap<PeerId, TrafficData>,
+
+    /// Number of transactions in the mempool of each peer at the end of the simulation.
+    pub mempool_sizes: HashMap<PeerId, usize>,
+
+    /// Number of transactions created during the simulation.
+    pub num_txs: usize,
+
+    /// Roles of peers that aren't honest.
+    pub roles: HashMap<PeerId, Role>,
+
+    /// Simulated time it took transactions to reach each peer since their creation, in seconds.
+    pub latencies: HashMap<PeerId, Vec<f64>>,
+
+    /// Simulated time it took blocks to reach each peer since they were found, in seconds.
+    pub block_latencies: HashMap<PeerId, Vec<f64>>,
+
+    /// Transaction each peer kept last for each output spent by conflicting transactions,
+    /// and the simulated time it did, in seconds.
+    pub conflict_versions: HashMap<PeerId, HashMap<TxId, (TxId, f64)>>,
+
+    /// How long honest peers disagreed on conflicting transactions, if there were any.
+    pub conflicts: Option<ConflictStats>,
+
+    /// Number of transactions in the mempool of each peer, by the peer that created them.
+    pub known_origins: HashMap<PeerId, HashMap<PeerId, u32>>,
+
+    /// Whether transactions made it into and out of eclipsed peers, if there was an attack.
+    pub eclipse: Option<EclipseStats>,
+
+    /// Width of short IDs in bits.
+    pub short_id_bits: u32,
+
+    /// Kind of reconciliation sketches.
+    pub sketch: SketchKind,
+
+    /// Misbehaviour scores honest peers gave to each other peer, summed up.
+    pub misbehaviour: HashMap<PeerId, u64>,
+
+    /// How well spy nodes inferred transaction origins, if there were any.
+    pub spy_estimate: Option<SpyEstimate>,
+
+    /// Every time a peer received a traced transaction it didn't know yet, ordered by time.
+    pub trace: Vec<TraceEvent>,
+}
+
+impl SimulationResults {
+    /// Total traffic sent and received by all peers, in bytes.
+    pub fn total_traffic(&self) -> u64 {
+        self.traffic
+            .values()
+            .fold(0, |v, next| v + (next.bytes_sent + next.bytes_received))
+    }
+
+    /// Total number of requests sent again after they were left unanswered.
+    pub fn requests_retried(&self) -> u64 {
+        self.traffic.values().map(|t| t.requests_retried).sum()
+    }
+
+    /// Total number of connections dropped since their peer misbehaved.
+    pub fn peers_disconnected(&self) -> u64 {
+        self.traffic.values().map(|t| t.peers_disconnected).sum()
+    }
+
+    /// Total number of transactions left out of sketches and announcements for a connection
+    /// since their short ID collided.
+    pub fn short_id_collisions(&self) -> u64 {
+        self.traffic.values().map(|t| t.short_id_collisions).sum()
+    }
+
+    /// Share of transactions put under short IDs for sketches and announcements
+    /// whose short ID collided.
+    pub fn short_id_collision_rate(&self) -> f64 {
+        let added = self
+            .traffic
+            .values()
+            .map(|t| t.short_ids_added)
+            .sum::<u64>();
+        if added == 0 {
+            return 0.0;
+        }
+
+        self.short_id_collisions() as f64 / added as f64
+    }
+
+    /// Total size of reconciliation sketches sent, in bytes.
+    pub fn sketch_bytes(&self) -> u64 {
+        self.traffic.values().map(|t| t.sketch_bytes).sum()
+    }
+
+    /// Share of sketch bytes saved by short IDs narrower than 64 bits. It's computed
+    /// from the sketch format for the same capacity rather than measured from traffic.
+    pub fn sketch_savings(&self) -> f64 {
+        // Compare sketches large enough for their size to be proportional to the capacity
+        let size = |bits| self.sketch.create(64, bits, None).serialized_size() as f64;
+        1.0 - size(self.short_id_bits) / size(64)
+    }
+
+    /// Total number of received sketches that couldn't be decoded.
+    pub fn decode_failures(&self) -> u64 {
+        self.traffic.values().map(|t| t.decode_failures).sum()
+    }
+
+    /// Share of received sketches that couldn't be decoded. Sketches of misbehaving
+    /// peers are counted too.
+    pub fn decode_failure_rate(&self) -> f64 {
+        let reconciliations = self
+            .traffic
+            .values()
+            .map(|t| t.reconciliations)
+            .sum::<u64>();
+        if reconciliations == 0 {
+            return 0.0;
+        }
+
+        self.decode_failures() as f64 / reconciliations as f64
+    }
+
+    /// Mean number of differences in sketches that could be decoded.
+    pub fn mean_set_difference(&self) -> f64 {
+        let decoded = self
+            .traffic
+            .values()
+            .map(|t| t.reconciliations - t.decode_failures)
+            .sum::<u64>();
+        if decoded == 0 {
+            return 0.0;
+        }
+
+        let differences = self
+            .traffic
+            .values()
+            .map(|t| t.set_differences)
+            .sum::<u64>();
+        differences as f64 / decoded as f64
+    }
+
+    /// Total number of transactions evicted from full mempools.
+    pub fn txs_evicted(&self) -> u64 {
+        self.traffic.values().map(|t| t.txs_evicted).sum()
+    }
+
+    /// Total number of transactions that expired in mempools.
+    pub fn txs_expired(&self) -> u64 {
+        self.traffic.values().map(|t| t.txs_expired).sum()
+    }
+
+    /// Total number of transactions mempools didn't let in since they were full or the
+    /// transactions paid too little.
+    pub fn txs_rejected(&self) -> u64 {
+        self.traffic.values().map(|t| t.txs_rejected).sum()
+    }
+
+    /// Total number of blocks mined.
+    pub fn blocks_mined(&self) -> u64 {
+        self.traffic.values().map(|t| t.blocks_mined).sum()
+    }
+
+    /// Largest number of transactions a single peer saw confirmed.
+    pub fn txs_confirmed(&self) -> u64 {
+        self.traffic
+            .values()
+            .map(|t| t.txs_confirmed)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Mean time it took blocks to reach honest peers, in simulated seconds.
+    pub fn mean_block_latency(&self) -> f64 {
+        let latencies = self
+            .block_latencies
+            .iter()
+            .filter(|(id, _)| self.is_honest(**id))
+            .flat_map(|(_, latencies)| latencies.iter().cloned())
+            .collect::<Vec<_>>();
+        if latencies.is_empty() {
+            return 0.0;
+        }
+
+        latencies.iter().sum::<f64>() / latencies.len() as f64
+    }
+
+    /// Total number of compact blocks received.
+    pub fn compact_blocks(&self) -> u64 {
+        self.traffic.values().map(|t| t.compact_blocks).sum()
+    }
+
+    /// Share of compact blocks reconstructed from the mempool without requesting transactions.
+    pub fn compact_block_reconstruction_rate(&self) -> f64 {
+        if self.compact_blocks() == 0 {
+            return 0.0;
+        }
+
+        let reconstructed = self
+            .traffic
+            .values()
+            .map(|t| t.compact_blocks_reconstructed)
+            .sum::<u64>();
+        reconstructed as f64 / self.compact_blocks() as f64
+    }
+
+    /// Total number of compact blocks whose short IDs matched wrong transactions.
+    pub fn compact_block_failures(&self) -> u64 {
+        self.traffic
+            .values()
+            .map(|t| t.compact_block_failures)
+            .sum()
+    }
+
+    /// Total number of extra round trips to request missing transactions of compact blocks.
+    pub fn block_round_trips(&self) -> u64 {
+        self.traffic.values().map(|t| t.block_round_trips).sum()
+    }
+
+    /// Total size of requests for missing transactions of compact blocks and responses to them.
+    pub fn block_extra_bytes(&self) -> u64 {
+        self.traffic.values().map(|t| t.block_extra_bytes).sum()
+    }
+
+    /// Total number of times transactions weren't announced to a connection since they
+    /// were below its fee filter.
+    pub fn txs_filtered(&self) -> u64 {
+        self.traffic.values().map(|t| t.txs_filtered).sum()
+    }
+
+    /// Total number of transactions received before the transactions they spend.
+    pub fn orphans(&self) -> u64 {
+        self.orphans_by_mechanism().iter().map(|(_, n)| n).sum()
+    }
+
+    /// Number of orphans received by each mechanism that delivered them.
+    pub fn orphans_by_mechanism(&self) -> Vec<(Mechanism, u64)> {
+        let mut orphans = HashMap::new();
+        for (&mechanism, &n) in self.traffic.values().flat_map(|t| &t.orphans) {
+            *orphans.entry(mechanism).or_default() += n;
+        }
+
+        let mut orphans = orphans.into_iter().collect::<Vec<_>>();
+        orphans.sort();
+        orphans
+    }
+
+    /// Share of transactions received from other peers that arrived before their parents.
+    pub fn orphan_rate(&self) -> f64 {
+        let received = self.latencies.values().map(|l| l.len() as u64).sum::<u64>();
+        if received == 0 {
+            return 0.0;
+        }
+
+        self.orphans() as f64 / received as f64
+    }
+
+    /// Total number of orphans processed once their parents arrived.
+    pub fn orphans_resolved(&self) -> u64 {
+        self.traffic.values().map(|t| t.orphans_resolved).sum()
+    }
+
+    /// Total number of orphans evicted from full orphan pools.
+    pub fn orphans_evicted(&self) -> u64 {
+        self.traffic.values().map(|t| t.orphans_evicted).sum()
+    }
+
+    /// Total number of transactions replaced by conflicting ones, along with their descendants.
+    pub fn txs_replaced(&self) -> u64 {
+        self.traffic.values().map(|t| t.txs_replaced).sum()
+    }
+
+    /// Total number of conflicting transactions that didn't pay enough to replace.
+    pub fn replacements_rejected(&self) -> u64 {
+        self.traffic.values().map(|t| t.replacements_rejected).sum()
+    }
+
+    /// Total number of messages dropped by injected faults or lost on lossy links.
+    pub fn messages_dropped(&self) -> u64 {
+        self.traffic.values().map(|t| t.messages_dropped).sum()
+    }
+
+    fn is_honest(&self, id: PeerId) -> bool {
+        !self.roles.contains_key(&id)
+    }
+
+    /// Average share of all created transactions that honest peers have in their mempools
+    /// or saw confirmed.
+    pub fn coverage(&self) -> f64 {
+        let sizes = self
+            .mempool_sizes
+            .iter()
+            .filter(|(id, _)| self.is_honest(**id))
+            .map(|(id, size)| {
+                let confirmed = self.traffic.get(id).map_or(0, |t| t.txs_confirmed);
+                *size + confirmed as usize
+            })
+            .collect::<Vec<_>>();
+
+        if self.num_txs == 0 || sizes.is_empty() {
+            return 0.0;
+        }
+
+        let known = sizes.iter().sum::<usize>() as f64;
+        known / (sizes.len() * self.num_txs) as f64
+    }
+
+    /// Latencies of transactions received by honest peers, sorted in ascending order.
+    fn honest_latencies(&self) -> Vec<f64> {
+        let mut latencies = self
+            .latencies
+            .iter()
+            .filter(|(id, _)| self.is_honest(**id))
+            .flat_map(|(_, latencies)| latencies.iter().cloned())
+            .collect::<Vec<_>>();
+        latencies.sort_by(|a, b| a.partial_cmp(b).expect("Latency is a number"));
+
+        latencies
+    }
+
+    /// Mean time it took transactions to reach honest peers, in simulated seconds.
+    pub fn mean_latency(&self) -> f64 {
+        let latencies = self.honest_latencies();
+        if latencies.is_empty() {
+            return 0.0;
+        }
+
+        latencies.iter().sum::<f64>() / latencies.len() as f64
+    }
+
+    /// Time it took the given share (`0.0..=1.0`) of transactions to reach honest peers,
+    /// in simulated seconds.
+    pub fn latency_percentile(&self, share: f64) -> f64 {
+        let latencies = self.honest_latencies();
+        if latencies.is_empty() {
+            return 0.0;
+        }
+
+        let index = ((latencies.len() - 1) as f64 * share).round() as usize;
+        latencies[index]
+    }
+}
+
+impl Display for SimulationResults {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        writeln!(f, "Traffic per peer:")?;
+        let mut traffic = self.traffic.iter().collect::<Vec<_>>();
+        traffic.sort_by_key(|(id, _)| u64::from(**id));
+
+        for (id, traffic) in traffic {
+            writeln!(
+                f,
+                "{:?}: {} ↑ {} ↓ (bytes)",
+                id, traffic.bytes_sent, traffic.bytes_received
+            )?;
+        }
+
+        writeln!(f, "Total traffic: {} bytes", self.total_traffic())?;
+        if self.messages_dropped() > 0 {
+            let bytes = self.traffic.values().map(|t| t.bytes_dropped).sum::<u64>();
+            writeln!(
+                f,
+                "Dropped: {} messages ({} bytes)",
+                self.messages_dropped(),
+                bytes
+            )?;
+        }
+        if !self.misbehaviour.is_empty() {
+            let mut scores = self.misbehaviour.iter().collect::<Vec<_>>();
+            scores.sort_by_key(|(id, _)| u64::from(**id));
+            let scores = scores
+                .iter()
+                .map(|(id, score)| format!("{:?}: {}", id, score))
+                .collect::<Vec<_>>();
+            writeln!(f, "Misbehaviour scores: {}", scores.join(", "))?;
+            writeln!(
+                f,
+                "Disconnected for misbehaviour: {}",
+                self.peers_disconnected()
+            )?;
+        }
+        if self.requests_retried() > 0 {
+            writeln!(f, "Retried requests: {}", self.requests_retried())?;
+        }
+        if self.short_id_collisions() > 0 {
+            writeln!(
+                f,
+                "Short ID collisions: {} ({:.4}%)",
+                self.short_id_collisions(),
+                self.short_id_collision_rate() * 100.0
+            )?;
+        }
+        let sketches = self.traffic.values().map(|t| t.sketches_sent).sum::<u64>();
+        if sketches > 0 {
+            write!(
+                f,
+                "Sketches: {} {} of {}-bit short IDs, {} bytes",
+                sketches,
+                self.sketch,
+                self.short_id_bits,
+                self.sketch_bytes()
+            )?;
+            if self.short_id_bits < 64 {
+                write!(
+                    f,
+                    " ({:.2}% smaller than 64-bit sketches of the same capacity)",
+                    self.sketch_savings() * 100.0
+                )?;
+            }
+            writeln!(f)?;
+        }
+        if self.decode_failures() > 0 {
+            writeln!(
+                f,
+                "Decode failures: {} ({:.2}%)",
+                self.decode_failures(),
+                self.decode_failure_rate() * 100.0
+            )?;
+        }
+        let reconciliations = self
+            .traffic
+            .values()
+            .map(|t| t.reconciliations)
+            .sum::<u64>();
+        if reconciliations > self.decode_failures() {
+            writeln!(
+                f,
+                "Set differences: mean {:.2} per reconciliation",
+                self.mean_set_difference()
+            )?;
+        }
+        if self.txs_evicted() + self.txs_expired() + self.txs_rejected() > 0 {
+            writeln!(
+                f,
+                "Mempool: {} evicted, {} expired, {} rejected",
+                self.txs_evicted(),
+                self.txs_expired(),
+                self.txs_rejected()
+            )?;
+        }
+        if self.txs_filtered() > 0 {
+            writeln!(
+                f,
+                "Fee filters: {} transactions not announced",
+                self.txs_filtered()
+            )?;
+        }
+        if self.orphans() > 0 {
+            let by_mechanism = self
+                .orphans_by_mechanism()
+                .iter()
+                .map(|(mechanism, n)| format!("{} {}", mechanism, n))
+                .collect::<Vec<_>>();
+            writeln!(
+                f,
+                "Orphans: {} ({:.2}% of received transactions), {} resolved, {} evicted, \
+                 by mechanism: {}",
+                self.orphans(),
+                self.orphan_rate() * 100.0,
+                self.orphans_resolved(),
+                self.orphans_evicted(),
+                by_mechanism.join(", ")
+            )?;
+        }
+        if self.txs_replaced() + self.replacements_rejected() > 0 {
+            writeln!(
+                f,
+                "Replacements: {} replaced, {} rejected",
+                self.txs_replaced(),
+                self.replacements_rejected()
+            )?;
+        }
+        if let Some(conflicts) = &self.conflicts {
+            writeln!(f, "Conflicts: {}", conflicts)?;
+        }
+        if self.blocks_mined() > 0 {
+            writeln!(
+                f,
+                "Blocks: {} mined, {} transactions confirmed, propagation mean {:.3}s",
+                self.blocks_mined(),
+                self.txs_confirmed(),
+                self.mean_block_latency()
+            )?;
+        }
+        if self.compact_blocks() > 0 {
+            writeln!(
+                f,
+                "Compact blocks: {} received, {:.2}% reconstructed from mempool, {} round trips, {} extra bytes",
+                self.compact_blocks(),
+                self.compact_block_reconstruction_rate() * 100.0,
+                self.block_round_trips(),
+                self.block_extra_bytes()
+            )?;
+        }
+        if self.compact_block_failures() > 0 {
+            writeln!(
+                f,
+                "Compact block short ID collisions: {}",
+                self.compact_block_failures()
+            )?;
+        }
+        writeln!(f, "Transactions: {}", self.num_txs)?;
+        write!(f, "Coverage: {:.2}%", self.coverage() * 100.0)?;
+        if !self.roles.is_empty() {
+            write!(f, " of honest peers ({} misbehave)", self.roles.len())?;
+        }
+        write!(
+            f,
+            "\nLatency: mean {:.3}s, 90th percentile {:.3}s",
+            self.mean_latency(),
+            self.latency_percentile(0.9)
+        )?;
+        if let Some(eclipse) = &self.eclipse {
+            write!(f, "\nEclipse: {}", eclipse)?;
+        }
+        if let Some(estimate) = &self.spy_estimate {
+            write!(f, "\nSpies: {}", estimate)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for SimulationBuilder {
+    fn default() -> Self {
+        SimulationBuilder {
+            relay: RelayKind::Flooding,
+            num_private_nodes: 8,
+            num_public_nodes: 2,
+            num_spies: 0,
+            seed: None,
+            time_scale: 1.0,
+            duration: None,
+            churn: None,
+            tx_interval: None,
+            reconciliation_interval: None,
+            reconciliation_capacity: None,
+            faults: FaultSchedule::new(),
+            network: NetworkModel::default(),
+            request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SEC),
+            max_retries: DEFAULT_MAX_RETRIES,
+            salted_short_ids: true,
+            short_id_bits: DEFAULT_SHORT_ID_BITS,
+            sketch: SketchKind::default(),
+            mempool: MempoolConfig::default(),
+            mining: None,
+            compact_blocks: false,
+            fee_rate: None,
+            min_fee_rate: None,
+            child_share: None,
+            max_orphans: DEFAULT_MAX_ORPHANS,
+            replace_share: None,
+            double_spend_share: None,
+            trace_sample: None,
+            roles: HashMap::new(),
+            role_fractions: Vec::new(),
+            eclipse: None,
+        }
+    }
+}
+
+impl SimulationBuilder {
+    /// Creates a builder for a flooding network of eight private and two public peers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Relay strategy used by every peer.
+    pub fn relay(mut self, relay: RelayKind) -> Self {
+        self.relay = relay;
+        self
+    }
+
+    /// Number of private nodes that don't have inbound connections.
+    pub fn num_private_nodes(mut self, num: u32) -> Self {
+        self.num_private_nodes = num;
+        self
+    }
+
+    /// Number of public nodes that have inbound connections.
+    pub fn num_public_nodes(mut self, num: u32) -> Self {
+        self.num_public_nodes = num;
+        self
+    }
+
+    /// Number of spy nodes. Spies are public nodes that relay like any other,
+    /// and log when they first see each transaction to guess which peer created it.
+    pub fn num_spies(mut self, num: u32) -> Self {
+        self.num_spies = num;
+        self
+    }
+
+    /// Seed for a random number generator.
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// How many times faster than the wall clock simulated time runs.
+    pub fn time_scale(mut self, time_scale: f64) -> Self {
+        self.time_scale = time_scale;
+        self
+    }
+
+    /// Simulated duration of the simulation, in seconds.
+    /// By default it's estimated from the time the network takes to settle.
+    pub fn duration(mut self, duration: Option<u64>) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Makes peers go offline and come back during the simulation.
+    pub fn churn(mut self, churn: Option<ChurnConfig>) -> Self {
+        self.churn = churn;
+        self
+    }
+
+    /// Makes private peers keep creating transactions at given intervals, in seconds,
+    /// instead of creating a single one at start.
+    pub fn tx_interval(mut self, tx_interval: Option<ValueDistribution>) -> Self {
+        self.tx_interval = tx_interval;
+        self
+    }
+
+    /// Makes set reconciling peers reconcile periodically instead of once.
+    /// Needed when transactions keep coming, e.g. with `tx_interval` or `churn`.
+    pub fn reconciliation_interval(mut self, interval: Option<Duration>) -> Self {
+        self.reconciliation_interval = interval;
+        self
+    }
+
+    /// Capacity of reconciliation sketches, at least 1. Equals the number of private nodes
+    /// by default.
+    pub fn reconciliation_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.reconciliation_capacity = capacity;
+        self
+    }
+
+    /// Injects a link failure or a network partition.
+    pub fn fault(mut self, fault: ScheduledFault) -> Self {
+        self.faults.add(fault);
+        self
+    }
+
+    /// Message loss and delays of every link that isn't configured with `link`.
+    pub fn link_model(mut self, model: LinkModel) -> Self {
+        self.network.default = model;
+        self
+    }
+
+    /// Message loss and delays of the link between peers `a` and `b`.
+    pub fn link(mut self, a: PeerId, b: PeerId, model: LinkModel) -> Self {
+        self.network.set_link(a, b, model);
+        self
+    }
+
+    /// Time peers wait for a response to a request before sending it again.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Number of times peers send an unanswered request again.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Whether short IDs are salted per connection. They are by default.
+    pub fn salted_short_ids(mut self, salted: bool) -> Self {
+        self.salted_short_ids = salted;
+        self
+    }
+
+    /// Width of short IDs in bits, from 2 to 64. Short IDs are 64-bit by default.
+    pub fn short_id_bits(mut self, bits: u32) -> Self {
+        self.short_id_bits = bits;
+        self
+    }
+
+    /// Kind of reconciliation sketches. Minisketch is used by default if it's compiled in.
+    pub fn sketch(mut self, kind: SketchKind) -> Self {
+        self.sketch = kind;
+        self
+    }
+
+    /// Size limit and expiry of mempools. Mempools are unlimited by default.
+    pub fn mempool(mut self, config: MempoolConfig) -> Self {
+        self.mempool = config;
+        self
+    }
+
+    /// Makes peers mine blocks that confirm transactions. No blocks are mined by default.
+    pub fn mining(mut self, mining: Option<MiningConfig>) -> Self {
+        self.mining = mining;
+        self
+    }
+
+    /// Whether blocks are relayed as BIP152 compact blocks. They're relayed in full by default.
+    pub fn compact_blocks(mut self, compact_blocks: bool) -> Self {
+        self.compact_blocks = compact_blocks;
+        self
+    }
+
+    /// Fee rates of created transactions in satoshis per 1000 bytes. All transactions
+    /// pay `DEFAULT_FEE_RATE` by default.
+    pub fn fee_rate(mut self, fee_rate: Option<ValueDistribution>) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Minimum fee rates peers draw for their mempools and announce with `FeeFilter`.
+    /// Peers accept transactions of any fee rate by default.
+    pub fn min_fee_rate(mut self, min_fee_rate: Option<ValueDistribution>) -> Self {
+        self.min_fee_rate = min_fee_rate;
+        self
+    }
+
+    /// Probability that a private peer's new transaction spends its previous unconfirmed
+    /// one. Transactions are independent by default.
+    pub fn child_share(mut self, child_share: Option<f64>) -> Self {
+        self.child_share = child_share;
+        self
+    }
+
+    /// Number of orphans each peer keeps at most. `DEFAULT_MAX_ORPHANS` by default.
+    pub fn max_orphans(mut self, max_orphans: usize) -> Self {
+        self.max_orphans = max_orphans;
+        self
+    }
+
+    /// Probability that a private peer replaces its previous unconfirmed transaction
+    /// with one that pays more. Transactions are never replaced by default.
+    pub fn replace_share(mut self, replace_share: Option<f64>) -> Self {
+        self.replace_share = replace_share;
+        self
+    }
+
+    /// Probability that a random peer the creator knows double-spends a new transaction.
+    /// Transactions are never double-spent by default.
+    pub fn double_spend_share(mut self, double_spend_share: Option<f64>) -> Self {
+        self.double_spend_share = double_spend_share;
+        self
+    }
+
+    /// Traces how a `sample` share of transactions reaches peers, see `SimulationResults::trace`.
+    /// Nothing is traced by default.
+    pub fn trace(mut self, sample: Option<f64>) -> Self {
+        self.trace_sample = sample;
+        self
+    }
+
+    /// Makes peer `id` behave according to `role`. Peers are honest by default.
+    pub fn role(mut self, id: PeerId, role: Role) -> Self {
+        self.roles.insert(id, role);
+        self
+    }
+
+    /// Makes a random `fraction` of public and private peers behave according to `role`.
+    /// Peers with a role set by `role` and spies are left out.
+    pub fn role_fraction(mut self, role: Role, fraction: f64) -> Self {
+        self.role_fractions.push((role, fraction));
+        self
+    }
+
+    /// Runs an eclipse attack on `victims`. The attacker controls a random
+    /// `attacker_share` of public peers.
+    pub fn eclipse(mut self, eclipse: Option<EclipseConfig>) -> Self {
+        self.eclipse = eclipse;
+        self
+    }
+
+    /// Roles of all peers that aren't honest.
+    fn assign_roles(&self) -> HashMap<PeerId, Role> {
+        let mut roles = self.roles.clone();
+        roles.retain(|_, role| !role.is_honest());
+
+        let mut rng = XorShiftRng::seed_from_u64(self.seed.unwrap_or(0));
+
+        if let Some(eclipse) = &self.eclipse {
+            let mut candidates = (0..self.num_public_nodes)
+                .map(PeerId::Public)
+                .filter(|id| !roles.contains_key(id))
+                .collect::<Vec<_>>();
+            candidates.shuffle(&mut rng);
+
+            let num = (self.num_public_nodes as f64 * eclipse.attacker_share).round() as usize;
+            for id in candidates.into_iter().take(num) {
+                roles.insert(id, Role::Eclipse(eclipse.victims.clone()));
+            }
+        }
+
+        let victims = self.eclipse.as_ref().map(|eclipse| &eclipse.victims);
+        let mut candidates = (0..self.num_public_nodes)
+            .map(PeerId::Public)
+            .chain((0..self.num_private_nodes).map(PeerId::Private))
+            .filter(|id| !roles.contains_key(id))
+            .filter(|id| !victims.is_some_and(|victims| victims.contains(id)))
+            .collect::<Vec<_>>();
+        let num_peers = candidates.len();
+
+        candidates.shuffle(&mut rng);
+
+        let mut candidates = candidates.into_iter();
+        for (role, fraction) in self.role_fractions.iter() {
+            let num = (num_peers as f64 * fraction).round() as usize;
+            for id in candidates.by_ref().take(num) {
+                roles.insert(id, role.clone());
+            }
+        }
+
+        roles
+    }
+
+    /// Time it takes for the network to settle, in seconds.
+    fn estimate_traffic_timeout_sec(&self) -> u64 {
+        (self.num_private_nodes as f32 * 0.57f32
+            + (self.num_public_nodes + self.num_spies) as f32 * 1f32) as u64
+    }
+
+    /// Starts a traffic counter that sends the results of the simulation to `results`
+    /// once it's over, and returns parameters of peers that report to it.
+    ///
+    /// Must be called from a running actix `System`.
+    pub(crate) fn peer_config(
+        &self,
+        registry: Arc<TxRegistry>,
+        results: mpsc::Sender<SimulationResults>,
+    ) -> PeerConfig {
+        let traffic_timeout = self
+            .duration
+            .unwrap_or_else(|| self.estimate_traffic_timeout_sec());
+        let clock = Clock::new(self.time_scale);
+        let tcounter = TrafficCounter::new(traffic_timeout, clock, results).start();
+
+        PeerConfig {
+            reconciliation_capacity: self
+                .reconciliation_capacity
+                .unwrap_or(self.num_private_nodes as usize)
+                .max(1),
+            traffic_counter_addr: tcounter,
+            traffic_timeout,
+            seed: self.seed,
+            registry,
+            clock,
+            churn: self.churn,
+            tx_interval: self.tx_interval,
+            reconciliation_interval: self.reconciliation_interval,
+            faults: Arc::new(self.faults.clone()),
+            network: Arc::new(self.network.clone()),
+            request_timeout: self.request_timeout,
+            max_retries: self.max_retries,
+            spies: None,
+            trace: None,
+            salted_short_ids: self.salted_short_ids,
+            short_id_bits: self.short_id_bits,
+            sketch: self.sketch,
+            mempool: self.mempool,
+            mining: self.mining.clone(),
+            compact_blocks: self.compact_blocks,
+            fee_rate: self.fee_rate,
+            min_fee_rate: self.min_fee_rate,
+            child_share: self.child_share,
+            max_orphans: self.max_orphans,
+            replace_share: self.replace_share,
+            double_spend_share: self.double_spend_share,
+        }
+    }
+
+    /// Runs the simulation until the network settles and returns its results.
+    ///
+    /// Blocks the current thread, since it drives its own actix `System`.
+    pub fn run(self) -> Result<SimulationResults, SimulationError> {
+        let registry = Arc::new(TxRegistry::new());
+        let (results_tx, results_rx) = mpsc::channel();
+        let roles = self.assign_roles();
+        let peer_roles = roles.clone();
+
+        // Spies come after honest public nodes
+        let spies = (self.num_public_nodes..self.num_public_nodes + self.num_spies)
+            .map(PeerId::Public)
+            .collect();
+        let spies = Some(Arc::new(SpyLog::new(spies))).filter(|_| self.num_spies > 0);
+        let peer_spies = spies.clone();
+        let trace = self
+            .trace_sample
+            .map(|sample| Arc::new(TxTrace::new(sample)));
+        let peer_trace = trace.clone();
+        let eclipse = self.eclipse.clone();
+        let short_id_bits = self.short_id_bits;
+        let sketch = self.sketch;
+
+        let peer_registry = registry.clone();
+        actix::System::run(move || {
+            let config = PeerConfig {
+                spies: peer_spies,
+                trace: peer_trace,
+                ..self.peer_config(peer_registry, results_tx)
+            };
+
+            let mut public_nodes = vec![];
+            let mut salts = HashMap::new();
+            for id in 0u32..self.num_public_nodes + self.num_spies {
+                let peer_id = PeerId::Public(id);
+                let mut peer = Peer::new(peer_id, self.relay.create(), config.clone());
+                peer.role = peer_roles.get(&peer_id).cloned().unwrap_or_default();
+                salts.insert(peer_id, peer.salt);
+                public_nodes.push((peer_id, peer.start()));
+            }
+
+            // Eclipse victims connect to attacker's peers first
+            let (attackers, honest): (Vec<_>, Vec<_>) = public_nodes
+                .iter()
+                .map(|(id, _)| *id)
+                .partition(|id| matches!(peer_roles.get(id), Some(Role::Eclipse(_))));
+            let all_public = public_nodes.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+            let eclipse = &self.eclipse;
+            let outbound_of = |id: &PeerId| match eclipse {
+                Some(eclipse) if eclipse.victims.contains(id) => {
+                    eclipse.victim_peers(&attackers, &honest)
+                }
+                _ => all_public.clone(),
+            };
+
+            let mut private_nodes = vec![];
+            for id in 0u32..self.num_private_nodes {
+                let peer_id = PeerId::Private(id);
+                let mut peer = Peer::new(peer_id, self.relay.create(), config.clone());
+                peer.role = peer_roles.get(&peer_id).cloned().unwrap_or_default();
+                let outbound = outbound_of(&peer_id);
+                for (id, pub_peer) in public_nodes.iter().filter(|(id, _)| outbound.contains(id)) {
+                    peer.add_outbound_peer(*id, pub_peer.clone(), salts[id]);
+                }
+
+                salts.insert(peer_id, peer.salt);
+
+                private_nodes.push((peer_id, peer.start(), outbound));
+            }
+
+            // Interconnect public nodes
+            for (this_id, public_peer) in public_nodes.iter() {
+                for (other_id, other_public_peer) in public_nodes.iter() {
+                    if *this_id != *other_id {
+                        other_public_peer.do_send(Connect {
+                            from_addr: public_peer.clone(),
+                            from_id: *this_id,
+                            salt: salts[this_id],
+                        });
+                    }
+                }
+            }
+
+            // Connect all private nodes to the all public nodes
+            for (this_id, private_peer, outbound) in private_nodes.iter() {
+                for (_other_id, other_public_peer) in
+                    public_nodes.iter().filter(|(id, _)| outbound.contains(id))
+                {
+                    other_public_peer.do_send(Connect {
+                        from_addr: private_peer.clone(),
+                        from_id: *this_id,
+                        salt: salts[this_id],
+                    });
+                }
+            }
+        })
+        .map_err(SimulationError::System)?;
+
+        let mut results = results_rx
+            .try_recv()
+            .map_err(|_| SimulationError::NoResults)?;
+        results.num_txs = registry.len();
+        if let Some(eclipse) = eclipse {
+            let num_attackers = roles
+                .values()
+                .filter(|role| matches!(role, Role::Eclipse(_)))
+                .count();
+            let honest = results
+                .known_origins
+                .keys()
+                .filter(|id| !roles.contains_key(id))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            results.eclipse = Some(EclipseStats::new(
+                &eclipse.victims,
+                num_attackers,
+                honest.into_iter(),
+                &results.known_origins,
+                &registry.count_by_origin(),
+            ));
+        }
+        results.short_id_bits = short_id_bits;
+        results.sketch = sketch;
+        results.roles = roles;
+        results.spy_estimate = spies.map(|spies| spies.first_spy_estimate(&registry));
+        results.trace = trace.map(|trace| trace.events()).unwrap_or_default();
+
+        let conflicts = registry.conflicts();
+        if !conflicts.is_empty() {
+            let honest = results
+                .conflict_versions
+                .iter()
+                .filter(|(id, _)| !results.roles.contains_key(id))
+                .map(|(id, versions)| (*id, versions.clone()))
+                .collect();
+            let created = |id| registry.created(id).as_secs_f64();
+            results.conflicts = Some(ConflictStats::new(&conflicts, created, &honest));
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SimulationBuilder;
+    use crate::adversary::Role;
+    use crate::faults::parse_partition;
+    use crate::peer::PeerId;
+    use crate::relay::RelayKind;
+    use crate::trace::{Mechanism, TraceEvent};
+    use crate::tx_registry::TxId;
+    use std::collections::{HashMap, HashSet};
+    use std::time::Duration;
+
+    fn run(relay: RelayKind) -> super::SimulationResults {
+        SimulationBuilder::new()
+            .relay(relay)
+            .num_private_nodes(4)
+            .num_public_nodes(2)
+            .seed(Some(1))
+            .time_scale(20.0)
+            .duration(Some(10))
+            .run()
+            .expect("Simulation results")
+    }
+
+    /// Runs a small network in which peer `id` has `role`, tracing every transaction.
+    fn run_with_role(relay: RelayKind, id: PeerId, role: Role) -> super::SimulationResults {
+        SimulationBuilder::new()
+            .relay(relay)
+            .num_private_nodes(4)
+            .num_public_nodes(2)
+            .seed(Some(1))
+            .time_scale(20.0)
+            .duration(Some(10))
+            .reconciliation_interval(Some(Duration::from_secs(2)))
+            .role(id, role)
+            .trace(Some(1.0))
+            .run()
+            .expect("Simulation results")
+    }
+
+    /// Creator of each traced transaction: the peer the first peer to receive it got it from.
+    fn origins(trace: &[TraceEvent]) -> HashMap<TxId, PeerId> {
+        let mut origins = HashMap::new();
+        for event in trace.iter() {
+            origins.entry(event.tx).or_insert(event.from);
+        }
+
+        origins
+    }
+
+    #[test]
+    pub fn test_flooding_simulation() {
+        let results = run(RelayKind::Flooding);
+        assert!(results.num_txs > 0);
+        assert!(results.total_traffic() > 0);
+        assert_eq!(results.coverage(), 1.0);
+    }
+
+    #[test]
+    pub fn test_erlay_simulation() {
+        let results = run(RelayKind::Erlay);
+        assert!(results.num_txs > 0);
+        assert!(results.total_traffic() > 0);
+        assert_eq!(results.coverage(), 1.0);
+    }
+
+    #[test]
+    pub fn test_erlay_heals_partition() {
+        let results = SimulationBuilder::new()
+            .relay(RelayKind::Erlay)
+            .seed(Some(1))
+            .time_scale(10.0)
+            .duration(Some(40))
+            .tx_interval(Some("exp:5".parse().unwrap()))
+            .reconciliation_interval(Some(Duration::from_secs(2)))
+            .fault(
+                parse_partition("pub0,priv0,priv1,priv2,priv3|pub1,priv4,priv5,priv6,priv7@5..20")
+                    .unwrap(),
+            )
+            .trace(Some(1.0))
+            .run()
+            .expect("Simulation results");
+
+        // Simulated time follows the wall clock, so the results depend on how busy the host
+        // is. Transactions created during the partition have 20 seconds to reach the other
+        // side, which holds however slow it is, unlike coverage of the latest transactions.
+        let mut receptions = HashMap::new();
+        for event in results.trace.iter() {
+            let (first, peers) = receptions
+                .entry(event.tx)
+                .or_insert_with(|| (event.time, HashSet::new()));
+            *first = event.time.min(*first);
+            peers.insert(event.peer);
+        }
+        let partitioned = receptions
+            .values()
+            .filter(|(first, _)| {
+                *first >= Duration::from_secs(5) && *first < Duration::from_secs(20)
+            })
+            .collect::<Vec<_>>();
+        assert!(!partitioned.is_empty());
+        assert!(partitioned
+            .iter()
+            .all(|(_, peers)| peers.len() == results.mempool_sizes.len() - 1));
+
+        // Sketches stop failing once peers fall back to announcing their mempools
+        assert!(results.decode_failure_rate() < 0.5);
+    }
+
+    #[test]
+    pub fn test_decode_failures_fall_back() {
+        let results = SimulationBuilder::new()
+            .relay(RelayKind::Erlay)
+            .seed(Some(1))
+            .time_scale(20.0)
+            .duration(Some(20))
+            .tx_interval(Some("exp:1".parse().unwrap()))
+            .reconciliation_interval(Some(Duration::from_secs(2)))
+            .reconciliation_capacity(Some(2))
+            .trace(Some(1.0))
+            .run()
+            .expect("Simulation results");
+
+        // Transactions a sketch too small to decode missed are requested after announcements
+        let fallbacks = results
+            .trace
+            .iter()
+            .filter(|event| event.mechanism == Mechanism::Fallback)
+            .count();
+        assert!(results.decode_failure_rate() > 0.0);
+        assert!(fallbacks > 0);
+    }
+
+    #[test]
+    pub fn test_black_hole() {
+        let black_hole = PeerId::Public(0);
+        for relay in [RelayKind::Flooding, RelayKind::Erlay].iter().cloned() {
+            let results = run_with_role(relay, black_hole, Role::BlackHole);
+
+            // It takes every transaction, but others only get them through honest peers
+            assert_eq!(
+                results.mempool_sizes[&black_hole], results.num_txs,
+                "{}",
+                relay
+            );
+            assert!(results.trace.iter().all(|event| event.from != black_hole));
+        }
+
+        // Private peers send new transactions to a single public peer, so only reconciliation
+        // recovers the ones sent to the black hole
+        let results = run_with_role(RelayKind::Erlay, black_hole, Role::BlackHole);
+        assert_eq!(results.coverage(), 1.0);
+    }
+
+    #[test]
+    pub fn test_free_rider() {
+        let free_rider = PeerId::Private(0);
+        let results = run_with_role(RelayKind::Erlay, free_rider, Role::FreeRider);
+
+        // It receives every transaction, but only sends its own
+        assert_eq!(results.mempool_sizes[&free_rider], results.num_txs);
+        let origins = origins(&results.trace);
+        assert!(results
+            .trace
+            .iter()
+            .filter(|event| event.from == free_rider)
+            .all(|event| origins[&event.tx] == free_rider));
+        assert_eq!(results.coverage(), 1.0);
+    }
+
+    #[test]
+    pub fn test_selective_relay() {
+        // The selective peer is the only public one, so everything goes through it
+        let (selective, ignored) = (PeerId::Public(0), PeerId::Private(1));
+        let role = Role::SelectiveRelay(vec![ignored].into_iter().collect());
+        for relay in [RelayKind::Flooding, RelayKind::Erlay].iter().cloned() {
+            let results = SimulationBuilder::new()
+                .relay(relay)
+                .num_private_nodes(4)
+                .num_public_nodes(1)
+                .seed(Some(1))
+                .time_scale(20.0)
+                .duration(Some(10))
+                .reconciliation_interval(Some(Duration::from_secs(2)))
+                .role(selective, role.clone())
+                .trace(Some(1.0))
+                .run()
+                .expect("Simulation results");
+
+            // Transactions of other peers are relayed as usual, ones of the ignored peer
+            // don't get past the selective peer
+            let origins = origins(&results.trace);
+            for event in results.trace.iter() {
+                if origins[&event.tx] == ignored {
+                    assert_eq!(event.peer, selective, "{}", relay);
+                } else {
+                    assert!(event.peer == selective || event.from == selective);
+                }
+            }
+            let relayed = results
+                .trace
+                .iter()
+                .filter(|event| event.from == selective)
+                .count();
+            // Transactions of the 3 other private peers reach the 3 private peers that didn't
+            // create them, all through the selective peer
+            assert_eq!(relayed, 3 * 3, "{}", relay);
+        }
+    }
+}
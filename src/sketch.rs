@@ -0,0 +1,263 @@
+//! Set sketches that `RecSet` reconciles with, and the backends implementing them.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[cfg(feature = "minisketch")]
+use minisketch_rs::Minisketch;
+
+use crate::iblt::Iblt;
+use crate::pinsketch::PinSketch;
+use crate::recset::RecSetError;
+
+/// Sketch of a set of short IDs.
+///
+/// Sketches of the same kind, capacity and width merge into a sketch of the symmetric
+/// difference of their sets, which decodes if it fits the capacity.
+pub trait SetSketch {
+    /// Adds `element` to the set, or removes it if it's already there.
+    fn add(&mut self, element: u64);
+
+    /// Size of the serialized sketch in bytes.
+    fn serialized_size(&self) -> usize;
+
+    /// Writes the sketch to `buf`, which must be at least `serialized_size` long.
+    fn serialize(&self, buf: &mut [u8]) -> Result<(), RecSetError>;
+
+    /// Reads the sketch from `serialized_size` bytes of `buf`.
+    fn deserialize(&mut self, buf: &[u8]);
+
+    /// Merges `other` into this sketch.
+    fn merge(&mut self, other: &Self) -> Result<(), RecSetError>;
+
+    /// Decodes elements of the set into `elements`, and returns their number.
+    fn decode(&self, elements: &mut [u64]) -> Result<usize, RecSetError>;
+}
+
+#[cfg(feature = "minisketch")]
+impl SetSketch for Minisketch {
+    fn add(&mut self, element: u64) {
+        Minisketch::add(self, element)
+    }
+
+    fn serialized_size(&self) -> usize {
+        Minisketch::serialized_size(self)
+    }
+
+    fn serialize(&self, buf: &mut [u8]) -> Result<(), RecSetError> {
+        Minisketch::serialize(self, buf).map_err(|_| RecSetError::InvalidSketch)
+    }
+
+    fn deserialize(&mut self, buf: &[u8]) {
+        Minisketch::deserialize(self, buf)
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<(), RecSetError> {
+        Minisketch::merge(self, other)
+            .map(|_| ())
+            .map_err(|_| RecSetError::InvalidSketch)
+    }
+
+    fn decode(&self, elements: &mut [u64]) -> Result<usize, RecSetError> {
+        Minisketch::decode(self, elements).map_err(|_| RecSetError::DecodeFailed)
+    }
+}
+
+/// Sketch backends that can be selected by name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SketchKind {
+    /// Bindings to the minisketch C++ library.
+    #[cfg(feature = "minisketch")]
+    Minisketch,
+
+    /// Pure-Rust PinSketch.
+    PinSketch,
+
+    /// Invertible Bloom Lookup Table, larger than PinSketch and may fail to decode
+    /// differences that fit its capacity.
+    Iblt,
+}
+
+impl SketchKind {
+    /// Backends compiled in.
+    pub fn all() -> Vec<SketchKind> {
+        vec![
+            #[cfg(feature = "minisketch")]
+            SketchKind::Minisketch,
+            SketchKind::PinSketch,
+            SketchKind::Iblt,
+        ]
+    }
+
+    /// Creates an empty sketch of `capacity` elements of `bits` bits.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0 and the sketch is minisketch.
+    pub fn create(self, capacity: usize, bits: u32, seed: Option<u64>) -> Sketch {
+        match self {
+            #[cfg(feature = "minisketch")]
+            SketchKind::Minisketch => {
+                let mut sketch =
+                    Minisketch::try_new(bits, 0, capacity).expect("Minisketch parameters");
+                if let Some(seed) = seed {
+                    sketch.set_seed(seed);
+                }
+
+                Sketch::Minisketch(sketch)
+            }
+            SketchKind::PinSketch => {
+                // Roots are found deterministically, so there's nothing to seed
+                let _ = seed;
+                Sketch::PinSketch(PinSketch::new(bits, capacity))
+            }
+            SketchKind::Iblt => Sketch::Iblt(Iblt::new(bits, capacity, seed)),
+        }
+    }
+}
+
+impl Default for SketchKind {
+    /// Minisketch if it's compiled in, PinSketch otherwise.
+    fn default() -> Self {
+        SketchKind::all()[0]
+    }
+}
+
+impl Display for SketchKind {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            #[cfg(feature = "minisketch")]
+            SketchKind::Minisketch => write!(f, "minisketch"),
+            SketchKind::PinSketch => write!(f, "pinsketch"),
+            SketchKind::Iblt => write!(f, "iblt"),
+        }
+    }
+}
+
+impl FromStr for SketchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SketchKind::all()
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| format!("unknown sketch: {}", s))
+    }
+}
+
+/// Parses capacity of sketches, a positive number of elements.
+pub fn parse_capacity(s: &str) -> Result<usize, String> {
+    s.parse::<usize>()
+        .ok()
+        .filter(|capacity| *capacity > 0)
+        .ok_or_else(|| format!("sketch capacity must be positive, got: {}", s))
+}
+
+/// Sketch of any of the backends.
+pub enum Sketch {
+    #[cfg(feature = "minisketch")]
+    Minisketch(Minisketch),
+    PinSketch(PinSketch),
+    Iblt(Iblt),
+}
+
+impl std::fmt::Debug for Sketch {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            #[cfg(feature = "minisketch")]
+            Sketch::Minisketch(_) => write!(f, "Minisketch"),
+            Sketch::PinSketch(sketch) => sketch.fmt(f),
+            Sketch::Iblt(sketch) => sketch.fmt(f),
+        }
+    }
+}
+
+impl SetSketch for Sketch {
+    fn add(&mut self, element: u64) {
+        match self {
+            #[cfg(feature = "minisketch")]
+            Sketch::Minisketch(sketch) => SetSketch::add(sketch, element),
+            Sketch::PinSketch(sketch) => sketch.add(element),
+            Sketch::Iblt(sketch) => sketch.add(element),
+        }
+    }
+
+    fn serialized_size(&self) -> usize {
+        match self {
+            #[cfg(feature = "minisketch")]
+            Sketch::Minisketch(sketch) => SetSketch::serialized_size(sketch),
+            Sketch::PinSketch(sketch) => sketch.serialized_size(),
+            Sketch::Iblt(sketch) => sketch.serialized_size(),
+        }
+    }
+
+    fn serialize(&self, buf: &mut [u8]) -> Result<(), RecSetError> {
+        match self {
+            #[cfg(feature = "minisketch")]
+            Sketch::Minisketch(sketch) => SetSketch::serialize(sketch, buf),
+            Sketch::PinSketch(sketch) => sketch.serialize(buf),
+            Sketch::Iblt(sketch) => sketch.serialize(buf),
+        }
+    }
+
+    fn deserialize(&mut self, buf: &[u8]) {
+        match self {
+            #[cfg(feature = "minisketch")]
+            Sketch::Minisketch(sketch) => SetSketch::deserialize(sketch, buf),
+            Sketch::PinSketch(sketch) => sketch.deserialize(buf),
+            Sketch::Iblt(sketch) => sketch.deserialize(buf),
+        }
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<(), RecSetError> {
+        match (self, other) {
+            #[cfg(feature = "minisketch")]
+            (Sketch::Minisketch(a), Sketch::Minisketch(b)) => SetSketch::merge(a, b),
+            (Sketch::PinSketch(a), Sketch::PinSketch(b)) => a.merge(b),
+            (Sketch::Iblt(a), Sketch::Iblt(b)) => a.merge(b),
+            _ => Err(RecSetError::InvalidSketch),
+        }
+    }
+
+    fn decode(&self, elements: &mut [u64]) -> Result<usize, RecSetError> {
+        match self {
+            #[cfg(feature = "minisketch")]
+            Sketch::Minisketch(sketch) => SetSketch::decode(sketch, elements),
+            Sketch::PinSketch(sketch) => sketch.decode(elements),
+            Sketch::Iblt(sketch) => sketch.decode(elements),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_capacity, SetSketch, SketchKind};
+
+    #[test]
+    pub fn test_sketch_kinds() {
+        for kind in SketchKind::all() {
+            assert_eq!(kind.to_string().parse::<SketchKind>(), Ok(kind));
+
+            let mut a = kind.create(8, 32, Some(42));
+            let mut b = kind.create(8, 32, Some(42));
+            for element in 1..=10u64 {
+                a.add(element * 7919);
+            }
+            for element in 4..=10u64 {
+                b.add(element * 7919);
+            }
+
+            a.merge(&b).expect("Merge");
+            let mut elements = vec![0u64; 8];
+            let num = a.decode(&mut elements).expect("Decode");
+            let mut decoded = elements[..num].to_vec();
+            decoded.sort();
+            assert_eq!(decoded, vec![7919, 2 * 7919, 3 * 7919], "{}", kind);
+        }
+
+        assert!("bloom".parse::<SketchKind>().is_err());
+        assert_eq!(parse_capacity("32"), Ok(32));
+        assert!(parse_capacity("0").is_err());
+        assert!(parse_capacity("-1").is_err());
+    }
+}
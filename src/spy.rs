@@ -0,0 +1,169 @@
+//! Spy nodes that log when they first see transactions, and a first-spy estimator
+//! that guesses which peer created each transaction from these logs.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::peer::PeerId;
+use crate::tx_registry::{TxId, TxRegistry};
+
+/// A spy received a transaction it didn't know about before.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Observation {
+    pub spy: PeerId,
+    pub from: PeerId,
+    pub time: Duration,
+}
+
+/// First-seen log shared by colluding spy nodes.
+#[derive(Debug, Default)]
+pub struct SpyLog {
+    spies: HashSet<PeerId>,
+    observations: Mutex<HashMap<TxId, Vec<Observation>>>,
+}
+
+impl SpyLog {
+    pub fn new(spies: HashSet<PeerId>) -> Self {
+        SpyLog {
+            spies,
+            observations: Default::default(),
+        }
+    }
+
+    pub fn is_spy(&self, id: PeerId) -> bool {
+        self.spies.contains(&id)
+    }
+
+    /// Records that `spy` first received transaction `tx` from `from` at simulated `time`.
+    pub fn record(&self, tx: TxId, spy: PeerId, from: PeerId, time: Duration) {
+        self.observations
+            .lock()
+            .expect("SpyLog lock")
+            .entry(tx)
+            .or_default()
+            .push(Observation { spy, from, time });
+    }
+
+    /// Guesses the origin of each transaction as the peer that delivered it to any spy first.
+    ///
+    /// Deliveries from other spies are ignored, since colluding spies know they didn't create it.
+    pub fn first_spy_guesses(&self) -> HashMap<TxId, PeerId> {
+        self.observations
+            .lock()
+            .expect("SpyLog lock")
+            .iter()
+            .filter_map(|(tx, observations)| {
+                observations
+                    .iter()
+                    .filter(|o| !self.is_spy(o.from))
+                    .min_by_key(|o| o.time)
+                    .map(|o| (*tx, o.from))
+            })
+            .collect()
+    }
+
+    /// Scores first-spy guesses against the actual origins of all registered transactions.
+    pub fn first_spy_estimate(&self, registry: &TxRegistry) -> SpyEstimate {
+        let guesses = self.first_spy_guesses();
+        let correct = guesses
+            .iter()
+            .filter(|(tx, origin)| registry.origin(**tx) == **origin)
+            .count();
+
+        SpyEstimate {
+            num_spies: self.spies.len(),
+            num_txs: registry.len(),
+            guesses: guesses.len(),
+            correct,
+        }
+    }
+}
+
+/// How well spies inferred the origins of transactions.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SpyEstimate {
+    pub num_spies: usize,
+
+    /// Number of transactions created during the simulation.
+    pub num_txs: usize,
+
+    /// Number of transactions spies guessed the origin of.
+    pub guesses: usize,
+
+    /// Number of guesses that were right.
+    pub correct: usize,
+}
+
+impl SpyEstimate {
+    /// Share of guesses that were right.
+    pub fn precision(&self) -> f64 {
+        if self.guesses == 0 {
+            return 0.0;
+        }
+
+        self.correct as f64 / self.guesses as f64
+    }
+
+    /// Share of all transactions whose origin was guessed right.
+    pub fn recall(&self) -> f64 {
+        if self.num_txs == 0 {
+            return 0.0;
+        }
+
+        self.correct as f64 / self.num_txs as f64
+    }
+}
+
+impl Display for SpyEstimate {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{} spies guessed {} of {} origins: precision {:.2}%, recall {:.2}%",
+            self.num_spies,
+            self.guesses,
+            self.num_txs,
+            self.precision() * 100.0,
+            self.recall() * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpyLog;
+    use crate::peer::PeerId;
+    use crate::tx_registry::TxRegistry;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_first_spy_estimate() {
+        let (spy_a, spy_b) = (PeerId::Public(2), PeerId::Public(3));
+        let log = SpyLog::new([spy_a, spy_b].iter().cloned().collect());
+        let registry = TxRegistry::new();
+
+        let ms = Duration::from_millis;
+        let tx1 = registry
+            .register(vec![1u8; 8], PeerId::Private(0), ms(0))
+            .id;
+        let tx2 = registry
+            .register(vec![2u8; 8], PeerId::Private(1), ms(0))
+            .id;
+        registry.register(vec![3u8; 8], PeerId::Private(2), ms(0));
+
+        // Right: first delivery comes from the origin
+        log.record(tx1, spy_a, PeerId::Public(0), ms(20));
+        log.record(tx1, spy_b, PeerId::Private(0), ms(10));
+        // Wrong: a relaying peer is faster than the origin, deliveries from spies don't count
+        log.record(tx2, spy_a, spy_b, ms(5));
+        log.record(tx2, spy_b, PeerId::Public(1), ms(15));
+        log.record(tx2, spy_a, PeerId::Private(1), ms(30));
+
+        let estimate = log.first_spy_estimate(&registry);
+        assert_eq!(estimate.guesses, 2);
+        assert_eq!(estimate.correct, 1);
+        assert_eq!(estimate.precision(), 0.5);
+        assert!((estimate.recall() - 1.0 / 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,157 @@
+//! Propagation trace: which peer received each transaction from which, when and how.
+
+use siphasher::sip::SipHasher;
+use std::fmt::{Display, Formatter};
+use std::hash::Hasher;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::peer::PeerId;
+use crate::tx_registry::TxId;
+
+/// How a transaction reached a peer.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mechanism {
+    /// Pushed by a flooding peer without being asked for.
+    Flood,
+
+    /// Pushed after set reconciliation found it missing.
+    Reconciliation,
+
+    /// Requested after an announcement of the whole mempool that replaced
+    /// a reconciliation whose sketch couldn't be decoded.
+    Fallback,
+
+    /// Requested after an announcement, or as the parent of an orphan.
+    Request,
+}
+
+impl Display for Mechanism {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        let name = match self {
+            Mechanism::Flood => "flood",
+            Mechanism::Reconciliation => "reconciliation",
+            Mechanism::Fallback => "fallback",
+            Mechanism::Request => "request",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Peer `peer` received transaction `tx` from peer `from` at simulated `time`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub tx: TxId,
+    pub peer: PeerId,
+    pub from: PeerId,
+    pub time: Duration,
+    pub mechanism: Mechanism,
+}
+
+impl TraceEvent {
+    /// Event as a single line of JSON, with simulated time in seconds.
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"tx":{},"peer":"{:?}","from":"{:?}","time":{:.6},"mechanism":"{}"}}"#,
+            self.tx.0,
+            self.peer,
+            self.from,
+            self.time.as_secs_f64(),
+            self.mechanism
+        )
+    }
+}
+
+/// Receptions of sampled transactions, shared by all peers.
+#[derive(Debug, Default)]
+pub struct TxTrace {
+    sample: f64,
+    events: Mutex<Vec<TraceEvent>>,
+}
+
+impl TxTrace {
+    /// Creates a trace of a `sample` share of transactions, between 0 and 1.
+    pub fn new(sample: f64) -> Self {
+        TxTrace {
+            sample,
+            events: Default::default(),
+        }
+    }
+
+    /// Whether transaction `id` is traced. The same transactions are sampled in every run.
+    pub fn is_sampled(&self, id: TxId) -> bool {
+        if self.sample >= 1.0 {
+            return true;
+        }
+
+        let mut hasher = SipHasher::new_with_keys(0x7472_6163, 0x6573);
+        hasher.write_u32(id.0);
+        (hasher.finish() as f64 / u64::MAX as f64) < self.sample
+    }
+
+    /// Records `event` if its transaction is sampled.
+    pub fn record(&self, event: TraceEvent) {
+        if self.is_sampled(event.tx) {
+            self.events.lock().expect("TxTrace lock").push(event);
+        }
+    }
+
+    /// All recorded events, ordered by time.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        let mut events = self.events.lock().expect("TxTrace lock").clone();
+        events.sort_by_key(|event| (event.time, event.tx));
+
+        events
+    }
+}
+
+/// Writes `events` as JSON Lines, one event per line.
+pub fn write_jsonl<W: Write>(events: &[TraceEvent], mut out: W) -> io::Result<()> {
+    for event in events {
+        writeln!(out, "{}", event.to_json())?;
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{write_jsonl, Mechanism, TraceEvent, TxTrace};
+    use crate::peer::PeerId;
+    use crate::tx_registry::TxId;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_trace() {
+        let event = |id, time| TraceEvent {
+            tx: TxId(id),
+            peer: PeerId::Private(1),
+            from: PeerId::Public(0),
+            time: Duration::from_millis(time),
+            mechanism: Mechanism::Reconciliation,
+        };
+
+        let trace = TxTrace::new(1.0);
+        trace.record(event(1, 2500));
+        trace.record(event(0, 1000));
+        assert_eq!(trace.events(), vec![event(0, 1000), event(1, 2500)]);
+
+        let mut out = vec![];
+        write_jsonl(&trace.events()[..1], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"tx\":0,\"peer\":\"priv1\",\"from\":\"pub0\",\"time\":1.000000,\"mechanism\":\"reconciliation\"}\n"
+        );
+
+        // Roughly the given share of transactions is sampled, the same ones every time
+        let trace = TxTrace::new(0.25);
+        let sampled = (0..1000).filter(|id| trace.is_sampled(TxId(*id))).count();
+        assert!(sampled > 200 && sampled < 300);
+        assert!((0..1000)
+            .filter(|id| trace.is_sampled(TxId(*id)))
+            .eq((0..1000).filter(|id| TxTrace::new(0.25).is_sampled(TxId(*id)))));
+        assert_eq!(trace.events(), vec![]);
+    }
+}
@@ -1,26 +1,106 @@
+use crate::clock::Clock;
 use crate::messages::TrafficReport;
 use crate::peer::PeerId;
+use crate::simulation::SimulationResults;
+use crate::trace::Mechanism;
+use crate::tx_registry::TxId;
 use actix::prelude::*;
 use std::collections::HashMap;
-use std::process;
+use std::sync::mpsc::Sender;
 use std::time::Duration;
 
 #[derive(Debug, Clone, Default)]
 pub struct TrafficData {
     pub bytes_received: u64,
     pub bytes_sent: u64,
+
+    /// Sent messages that were dropped by injected faults or lost on lossy links.
+    pub messages_dropped: u64,
+    pub bytes_dropped: u64,
+
+    /// Requests sent again after they were left unanswered.
+    pub requests_retried: u64,
+
+    /// Connections dropped since their peer misbehaved.
+    pub peers_disconnected: u64,
+
+    /// Transactions put under the short IDs of a connection for sketches and announcements,
+    /// and ones of them left out since their short ID collided.
+    pub short_ids_added: u64,
+    pub short_id_collisions: u64,
+
+    /// Reconciliation sketches sent, and their size in bytes.
+    pub sketches_sent: u64,
+    pub sketch_bytes: u64,
+
+    /// Sketches received from other peers, misbehaving ones included, ones of them that
+    /// couldn't be decoded, and the total number of differences in the decoded ones.
+    pub reconciliations: u64,
+    pub decode_failures: u64,
+    pub set_differences: u64,
+
+    /// Transactions evicted from the mempool, expired, and not let in since it was full
+    /// or they paid less than its minimum fee rate.
+    pub txs_evicted: u64,
+    pub txs_expired: u64,
+    pub txs_rejected: u64,
+
+    /// Blocks mined, and transactions confirmed by blocks the peer received or mined.
+    pub blocks_mined: u64,
+    pub txs_confirmed: u64,
+
+    /// Compact blocks received, ones of them reconstructed from the mempool alone,
+    /// and ones whose short IDs matched wrong transactions.
+    pub compact_blocks: u64,
+    pub compact_blocks_reconstructed: u64,
+    pub compact_block_failures: u64,
+
+    /// Requests for missing transactions of compact blocks, and bytes they took both ways.
+    pub block_round_trips: u64,
+    pub block_extra_bytes: u64,
+
+    /// Transactions not announced to connections since they were below their fee filters.
+    pub txs_filtered: u64,
+
+    /// Transactions received before the transactions they spend by the mechanism that
+    /// delivered them, ones of them resolved once their parents arrived, and ones evicted
+    /// from the orphan pool before that.
+    pub orphans: HashMap<Mechanism, u64>,
+    pub orphans_resolved: u64,
+    pub orphans_evicted: u64,
+
+    /// Transactions replaced by conflicting ones, along with their descendants,
+    /// and conflicting transactions that didn't pay enough to replace.
+    pub txs_replaced: u64,
+    pub replacements_rejected: u64,
 }
 
 pub struct TrafficCounter {
     pub traffic: HashMap<PeerId, TrafficData>,
+    pub mempool_sizes: HashMap<PeerId, usize>,
+    pub misbehaviour: HashMap<PeerId, u64>,
+    pub latencies: HashMap<PeerId, Vec<f64>>,
+    pub block_latencies: HashMap<PeerId, Vec<f64>>,
+    pub conflict_versions: HashMap<PeerId, HashMap<TxId, (TxId, f64)>>,
+    pub known_origins: HashMap<PeerId, HashMap<PeerId, u32>>,
     pub traffic_timeout_sec: u64,
+    clock: Clock,
+    results: Sender<SimulationResults>,
 }
 
 impl TrafficCounter {
-    pub fn new(timeout: u64) -> Self {
+    pub fn new(timeout: u64, clock: Clock, results: Sender<SimulationResults>) -> Self {
         TrafficCounter {
             traffic: Default::default(),
+            mempool_sizes: Default::default(),
+            misbehaviour: Default::default(),
+            latencies: Default::default(),
+            block_latencies: Default::default(),
+            conflict_versions: Default::default(),
+            known_origins: Default::default(),
             traffic_timeout_sec: timeout,
+            clock,
+            results,
         }
     }
 }
@@ -29,27 +109,23 @@ impl Actor for TrafficCounter {
     type Context = actix::Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_later(Duration::from_secs(self.traffic_timeout_sec), |act, _| {
-            let total_traffic = act
-                .traffic
-                .values()
-                .fold(0, |v, next| v + (next.bytes_sent + next.bytes_received));
-
-            println!("{}", total_traffic);
-
-            /*
-            println!("Traffic per peer:");
-            let mut traffic = act.traffic.iter().collect::<Vec<_>>();
-            traffic.sort_by_key(|(id, _)| Into::<u64>::into(**id));
-
-            for (id, traffic) in traffic {
-                println!(
-                    "{:?}: {} ↑ {} ↓ (bytes)",
-                    id, traffic.bytes_sent, traffic.bytes_received
-                );
-            }*/
-
-            process::exit(0);
+        let timeout = self
+            .clock
+            .real(Duration::from_secs(self.traffic_timeout_sec));
+        ctx.run_later(timeout, |act, _| {
+            let results = SimulationResults {
+                traffic: std::mem::take(&mut act.traffic),
+                mempool_sizes: std::mem::take(&mut act.mempool_sizes),
+                misbehaviour: std::mem::take(&mut act.misbehaviour),
+                latencies: std::mem::take(&mut act.latencies),
+                block_latencies: std::mem::take(&mut act.block_latencies),
+                conflict_versions: std::mem::take(&mut act.conflict_versions),
+                known_origins: std::mem::take(&mut act.known_origins),
+                ..Default::default()
+            };
+
+            let _ = act.results.send(results);
+            System::current().stop();
         });
     }
 }
@@ -58,15 +134,51 @@ impl Handler<TrafficReport> for TrafficCounter {
     type Result = ();
 
     fn handle(&mut self, msg: TrafficReport, _: &mut Self::Context) -> Self::Result {
-        if !self.traffic.contains_key(&msg.from_id) {
-            self.traffic.insert(msg.from_id, TrafficData::default());
-        }
-
-        if let Some(data) = self.traffic.get_mut(&msg.from_id) {
-            *data = TrafficData {
+        self.traffic.insert(
+            msg.from_id,
+            TrafficData {
                 bytes_received: msg.bytes_received,
                 bytes_sent: msg.bytes_sent,
-            };
+                messages_dropped: msg.messages_dropped,
+                bytes_dropped: msg.bytes_dropped,
+                requests_retried: msg.requests_retried,
+                peers_disconnected: msg.peers_disconnected,
+                short_ids_added: msg.short_ids_added,
+                short_id_collisions: msg.short_id_collisions,
+                sketches_sent: msg.sketches_sent,
+                sketch_bytes: msg.sketch_bytes,
+                reconciliations: msg.reconciliations,
+                decode_failures: msg.decode_failures,
+                set_differences: msg.set_differences,
+                txs_evicted: msg.txs_evicted,
+                txs_expired: msg.txs_expired,
+                txs_rejected: msg.txs_rejected,
+                blocks_mined: msg.blocks_mined,
+                txs_confirmed: msg.txs_confirmed,
+                compact_blocks: msg.compact_blocks,
+                compact_blocks_reconstructed: msg.compact_blocks_reconstructed,
+                compact_block_failures: msg.compact_block_failures,
+                block_round_trips: msg.block_round_trips,
+                block_extra_bytes: msg.block_extra_bytes,
+                txs_filtered: msg.txs_filtered,
+                orphans: msg.orphans,
+                orphans_resolved: msg.orphans_resolved,
+                orphans_evicted: msg.orphans_evicted,
+                txs_replaced: msg.txs_replaced,
+                replacements_rejected: msg.replacements_rejected,
+            },
+        );
+        self.mempool_sizes
+            .insert(msg.from_id, msg.mempool_size as usize);
+        self.latencies.insert(msg.from_id, msg.latencies);
+        self.block_latencies
+            .insert(msg.from_id, msg.block_latencies);
+        self.conflict_versions
+            .insert(msg.from_id, msg.conflict_versions);
+        self.known_origins.insert(msg.from_id, msg.known_origins);
+
+        for (id, score) in msg.misbehaviour {
+            *self.misbehaviour.entry(id).or_default() += score;
         }
     }
 }
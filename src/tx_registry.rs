@@ -0,0 +1,293 @@
+//! Shared storage of transaction bodies.
+//!
+//! Every transaction is stored once, and peers refer to it with a small `Tx` handle
+//! made of its `TxId`, logical size and fee rate.
+
+use sha2::{Digest, Sha256};
+use siphasher::sip::SipHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::mempool::DEFAULT_FEE_RATE;
+use crate::messages::Tx;
+use crate::peer::PeerId;
+use crate::recset::ShortId;
+use crate::short_ids::ShortIdKey;
+
+/// Index of a transaction in the `TxRegistry`.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TxId(pub u32);
+
+/// Transaction body along with its hash, its precomputed unsalted short ID, the peer
+/// that created it and when, the unconfirmed transaction it spends, if any, and the
+/// output it spends, identified by the first transaction that spent it.
+struct TxRecord {
+    body: Box<[u8]>,
+    hash: [u8; 32],
+    short_id: u64,
+    origin: PeerId,
+    created: Duration,
+    parent: Option<TxId>,
+    outpoint: TxId,
+}
+
+impl ShortId<u64> for [u8; 32] {
+    fn short_id(&self, key: ShortIdKey) -> u64 {
+        let mut hasher = SipHasher::new_with_keys(key.0, key.1);
+        hasher.write(self);
+        hasher.finish()
+    }
+}
+
+/// Registry of all transactions in the simulation, shared between peers.
+#[derive(Default)]
+pub struct TxRegistry {
+    txs: RwLock<Vec<TxRecord>>,
+}
+
+impl TxRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores transaction `body` created by peer `origin` at simulated time `created`
+    /// and returns a handle to it.
+    pub fn register(&self, body: Vec<u8>, origin: PeerId, created: Duration) -> Tx {
+        self.insert(body, None, origin, created, None, None)
+    }
+
+    /// Stores a transaction that spends an output of transaction `parent`.
+    pub fn register_child(
+        &self,
+        body: Vec<u8>,
+        origin: PeerId,
+        created: Duration,
+        parent: TxId,
+    ) -> Tx {
+        self.insert(body, None, origin, created, Some(parent), None)
+    }
+
+    /// Stores a transaction that spends the same output as transaction `original`,
+    /// either replacing it or double-spending it.
+    pub fn register_conflicting(
+        &self,
+        body: Vec<u8>,
+        origin: PeerId,
+        created: Duration,
+        original: TxId,
+    ) -> Tx {
+        let (parent, outpoint) = {
+            let txs = self.txs.read().expect("TxRegistry lock");
+            let original = &txs[original.0 as usize];
+            (original.parent, original.outpoint)
+        };
+        self.insert(body, None, origin, created, parent, Some(outpoint))
+    }
+
+    /// Stores a transaction whose unsalted short ID collides with the one of transaction
+    /// `target`, as if `origin` ground its body until they matched.
+    pub fn register_colliding(
+        &self,
+        body: Vec<u8>,
+        origin: PeerId,
+        created: Duration,
+        target: TxId,
+    ) -> Tx {
+        let short_id = self.short_id(target, ShortIdKey::UNSALTED);
+        self.insert(body, Some(short_id), origin, created, None, None)
+    }
+
+    /// Stores a transaction, under its own unsalted short ID unless `short_id` is given.
+    fn insert(
+        &self,
+        body: Vec<u8>,
+        short_id: Option<u64>,
+        origin: PeerId,
+        created: Duration,
+        parent: Option<TxId>,
+        outpoint: Option<TxId>,
+    ) -> Tx {
+        let size = body.len() as u32;
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&Sha256::digest(&body));
+        let short_id = short_id.unwrap_or_else(|| hash.short_id(ShortIdKey::UNSALTED));
+
+        let mut txs = self.txs.write().expect("TxRegistry lock");
+        let id = TxId(txs.len() as u32);
+        txs.push(TxRecord {
+            body: body.into_boxed_slice(),
+            hash,
+            short_id,
+            origin,
+            created,
+            parent,
+            outpoint: outpoint.unwrap_or(id),
+        });
+
+        Tx {
+            id,
+            size,
+            fee_rate: DEFAULT_FEE_RATE,
+        }
+    }
+
+    /// Short ID of a registered transaction under the SipHash `key` of a connection.
+    /// Salted short IDs are computed from the 32-byte hash rather than the whole body.
+    pub fn short_id(&self, id: TxId, key: ShortIdKey) -> u64 {
+        let txs = self.txs.read().expect("TxRegistry lock");
+        let tx = &txs[id.0 as usize];
+
+        if key == ShortIdKey::UNSALTED {
+            tx.short_id
+        } else {
+            tx.hash.short_id(key)
+        }
+    }
+
+    /// SHA-256 hash of a registered transaction body, which identifies it like a txid.
+    pub fn hash(&self, id: TxId) -> [u8; 32] {
+        self.txs.read().expect("TxRegistry lock")[id.0 as usize].hash
+    }
+
+    /// Peer that created a registered transaction.
+    pub fn origin(&self, id: TxId) -> PeerId {
+        self.txs.read().expect("TxRegistry lock")[id.0 as usize].origin
+    }
+
+    /// Transaction a registered transaction spends, if it was created as a child.
+    pub fn parent(&self, id: TxId) -> Option<TxId> {
+        self.txs.read().expect("TxRegistry lock")[id.0 as usize].parent
+    }
+
+    /// Output a registered transaction spends, identified by the first transaction
+    /// that spent it. Transactions that spend the same output conflict.
+    pub fn outpoint(&self, id: TxId) -> TxId {
+        self.txs.read().expect("TxRegistry lock")[id.0 as usize].outpoint
+    }
+
+    /// Conflicting transactions by the output they spend, in the order they were created.
+    pub fn conflicts(&self) -> HashMap<TxId, Vec<TxId>> {
+        let mut conflicts = HashMap::<TxId, Vec<TxId>>::new();
+        for (i, tx) in self.txs.read().expect("TxRegistry lock").iter().enumerate() {
+            let id = TxId(i as u32);
+            if tx.outpoint != id {
+                conflicts
+                    .entry(tx.outpoint)
+                    .or_insert_with(|| vec![tx.outpoint])
+                    .push(id);
+            }
+        }
+
+        conflicts
+    }
+
+    /// Simulated time a registered transaction was created at.
+    pub fn created(&self, id: TxId) -> Duration {
+        self.txs.read().expect("TxRegistry lock")[id.0 as usize].created
+    }
+
+    /// Number of registered transactions created by each peer.
+    pub fn count_by_origin(&self) -> HashMap<PeerId, usize> {
+        let mut counts = HashMap::new();
+        for tx in self.txs.read().expect("TxRegistry lock").iter() {
+            *counts.entry(tx.origin).or_default() += 1;
+        }
+
+        counts
+    }
+
+    /// Copy of a registered transaction body.
+    pub fn body(&self, id: TxId) -> Vec<u8> {
+        self.txs.read().expect("TxRegistry lock")[id.0 as usize]
+            .body
+            .to_vec()
+    }
+
+    /// Number of registered transactions.
+    pub fn len(&self) -> usize {
+        self.txs.read().expect("TxRegistry lock").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TxRegistry;
+    use crate::peer::PeerId;
+    use crate::recset::ShortId;
+    use crate::short_ids::ShortIdKey;
+    use sha2::{Digest, Sha256};
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_register() {
+        let registry = TxRegistry::new();
+
+        let a = registry.register(vec![1u8; 1024], PeerId::Private(0), Duration::from_secs(0));
+        let b = registry.register(vec![2u8; 250], PeerId::Private(1), Duration::from_secs(3));
+
+        assert_ne!(a.id, b.id);
+        assert_eq!(a.size, 1024);
+        assert_eq!(b.size, 250);
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.body(b.id), vec![2u8; 250]);
+        assert_eq!(registry.origin(b.id), PeerId::Private(1));
+        assert_eq!(registry.created(b.id), Duration::from_secs(3));
+        assert_eq!(registry.hash(a.id)[..], Sha256::digest(&[1u8; 1024])[..]);
+        assert_eq!(
+            registry.short_id(a.id, ShortIdKey::UNSALTED),
+            registry.hash(a.id).short_id(ShortIdKey::UNSALTED)
+        );
+        assert_eq!(std::mem::size_of_val(&a), 12);
+
+        let child = registry.register_child(
+            vec![4u8; 100],
+            PeerId::Private(0),
+            Duration::from_secs(5),
+            a.id,
+        );
+        assert_eq!(registry.parent(child.id), Some(a.id));
+        assert_eq!(registry.parent(a.id), None);
+
+        // Replacements spend the same output as the original, from the same parent
+        let replacement = registry.register_conflicting(
+            vec![5u8; 100],
+            PeerId::Private(1),
+            Duration::from_secs(6),
+            child.id,
+        );
+        assert_eq!(registry.outpoint(replacement.id), child.id);
+        assert_eq!(registry.outpoint(child.id), child.id);
+        assert_eq!(registry.parent(replacement.id), Some(a.id));
+        assert_eq!(
+            registry.conflicts().get(&child.id),
+            Some(&vec![child.id, replacement.id])
+        );
+
+        // Colliding transactions only collide without a salt
+        let c = registry.register_colliding(
+            vec![3u8; 250],
+            PeerId::Public(0),
+            Duration::from_secs(4),
+            a.id,
+        );
+        let salted = ShortIdKey::from_salts(1, 2);
+        assert_eq!(
+            registry.short_id(c.id, ShortIdKey::UNSALTED),
+            registry.short_id(a.id, ShortIdKey::UNSALTED)
+        );
+        assert_ne!(
+            registry.short_id(c.id, salted),
+            registry.short_id(a.id, salted)
+        );
+        assert_eq!(
+            registry.short_id(c.id, salted),
+            registry.hash(c.id).short_id(salted)
+        );
+    }
+}